@@ -0,0 +1,476 @@
+use lsp_types::TextEdit;
+use rust_analyzer_syntax::ast::{self, HasAttrs, HasVisibility};
+use rust_analyzer_syntax::{
+    AstNode, SourceFile, SyntaxKind, SyntaxNode, SyntaxToken, TextRange, TextSize,
+};
+
+use crate::database::manifest::dependency_name;
+use crate::database::resolve::{
+    is_visible, module_at, module_distance, path_segments, resolve_path,
+};
+use crate::database::scope::{find_block_item, find_local};
+use crate::database::{Database, FileData, ItemData, ItemKind, ModulePath, Visibility};
+use crate::utils::range;
+
+/// Names that are in scope everywhere through the standard prelude, or as primitive types. Code using
+/// them usually means the prelude's, even if the workspace has something with the same name.
+const PRELUDE_NAMES: &[&str] = &[
+    "AsMut",
+    "AsRef",
+    "AsyncFn",
+    "AsyncFnMut",
+    "AsyncFnOnce",
+    "Box",
+    "Clone",
+    "Copy",
+    "Default",
+    "DoubleEndedIterator",
+    "Drop",
+    "Eq",
+    "Err",
+    "ExactSizeIterator",
+    "Extend",
+    "Fn",
+    "FnMut",
+    "FnOnce",
+    "From",
+    "FromIterator",
+    "Future",
+    "Into",
+    "IntoFuture",
+    "IntoIterator",
+    "Iterator",
+    "None",
+    "Ok",
+    "Option",
+    "Ord",
+    "PartialEq",
+    "PartialOrd",
+    "Result",
+    "Send",
+    "Sized",
+    "Some",
+    "String",
+    "Sync",
+    "ToOwned",
+    "ToString",
+    "TryFrom",
+    "TryInto",
+    "Unpin",
+    "Vec",
+    "drop",
+    "bool",
+    "char",
+    "f16",
+    "f32",
+    "f64",
+    "f128",
+    "i8",
+    "i16",
+    "i32",
+    "i64",
+    "i128",
+    "isize",
+    "str",
+    "u8",
+    "u16",
+    "u32",
+    "u64",
+    "u128",
+    "usize",
+];
+
+/// Whether `name` is also the name of something from the standard prelude or a primitive type
+pub fn is_prelude_name(name: &str) -> bool {
+    PRELUDE_NAMES.contains(&name)
+}
+
+/// Something that can be imported to bring a name into scope
+pub struct ImportCandidate<'db> {
+    /// The path to write in the `use`, like `["crate", "net", "Client"]`
+    pub path: Vec<String>,
+    /// The imported item, or `None` for modules
+    pub item: Option<&'db ItemData>,
+}
+
+/// Every item and module outside of `from` whose name matches, and which is visible from it. The
+/// closest come first, with public ones ahead of restricted ones at the same distance, since those
+/// are meant to be used elsewhere.
+pub fn import_candidates<'db>(
+    db: &'db Database,
+    from: &ModulePath,
+    matches: impl Fn(&str) -> bool,
+) -> Vec<ImportCandidate<'db>> {
+    let items = db
+        .items
+        .iter()
+        .filter(|(path, _)| &path.module != from && matches(&path.name))
+        .filter_map(|(path, defs)| {
+            let data = defs.first()?;
+            // Only exported macros can be imported, and they live at the crate root regardless
+            if matches!(data.kind, ItemKind::Macro | ItemKind::ExternCrate)
+                || !is_visible(&data.visibility, &path.module, from)
+            {
+                return None;
+            }
+
+            let rank = visibility_rank(&data.visibility);
+            Some((rank, path.module.clone(), &path.name, Some(data)))
+        });
+    // Modules are imported by their own path, so it's their parent that they're in, and their
+    // visibility is that of their `mod` declaration
+    let modules = db
+        .files
+        .values()
+        .flat_map(|file| &file.modules)
+        .filter_map(|include| {
+            let parent = include.module.parent()?;
+            let visible = is_visible(&include.visibility, &parent, from);
+            (&parent != from && visible && matches(&include.name)).then_some((
+                visibility_rank(&include.visibility),
+                parent,
+                &include.name,
+                None,
+            ))
+        });
+
+    // Other crates can only be named when they're dependencies, by whatever name they're given
+    let mut candidates = items
+        .chain(modules)
+        .filter_map(|(rank, module, name, item)| {
+            let distance = module_distance(from, &module);
+            let mut path = match module.crate_ == from.crate_ {
+                true => vec!["crate".to_string()],
+                false => vec![dependency_name(db, &from.crate_, &module.crate_)?],
+            };
+            path.extend(module.segments);
+            path.push(name.clone());
+            Some(((distance, rank), ImportCandidate { path, item }))
+        })
+        .collect::<Vec<_>>();
+
+    candidates
+        .sort_by(|(a_rank, a), (b_rank, b)| a_rank.cmp(b_rank).then_with(|| a.path.cmp(&b.path)));
+    // Modules declared in several files (through `#[cfg_attr(.., path)]`) only need importing once
+    candidates.dedup_by(|(_, a), (_, b)| a.path == b.path && a.item.is_none() && b.item.is_none());
+    candidates
+        .into_iter()
+        .map(|(_, candidate)| candidate)
+        .collect()
+}
+
+/// Public things sort ahead of restricted ones
+fn visibility_rank(visibility: &Visibility) -> u32 {
+    match visibility {
+        Visibility::Public => 0,
+        Visibility::Crate => 1,
+        _ => 2,
+    }
+}
+
+/// The name `token` refers to, if it's the start of a path (outside of `use`s, attributes and macro
+/// calls) that doesn't resolve to anything and isn't imported, so importing it might help
+pub fn unresolved_name(db: &Database, file: &FileData, token: &SyntaxToken) -> Option<String> {
+    if token.kind() != SyntaxKind::IDENT
+        || find_local(token).is_some()
+        || find_block_item(token).is_some()
+    {
+        return None;
+    }
+
+    let path = token
+        .parent()
+        .and_then(ast::NameRef::cast)?
+        .syntax()
+        .parent()
+        .and_then(ast::PathSegment::cast)?
+        .parent_path();
+    let in_use_or_attr = path
+        .syntax()
+        .ancestors()
+        .any(|node| matches!(node.kind(), SyntaxKind::USE | SyntaxKind::ATTR));
+    // Macros live in their own namespace, which nothing importable is in
+    let is_macro = path
+        .syntax()
+        .parent()
+        .is_some_and(|parent| parent.kind() == SyntaxKind::MACRO_CALL);
+    if path.qualifier().is_some() || in_use_or_attr || is_macro {
+        return None;
+    }
+
+    // Imports from outside the workspace don't resolve, but still bring the name into scope
+    let name = token.text().to_string();
+    let module = module_at(&file.module_path, path.syntax());
+    let imported = file
+        .imports
+        .iter()
+        .any(|import| import.module == module && import.name.as_ref() == Some(&name));
+    (!imported && resolve_path(db, &module, std::slice::from_ref(&name)).is_none()).then_some(name)
+}
+
+/// The edit importing `path` into the module `node` is in. It's merged into an existing `use`
+/// sharing the path's module when there is one, and added as a new `use` otherwise.
+pub fn import_edit(file: &FileData, node: &SyntaxNode, path: &[String]) -> TextEdit {
+    let (text_range, new_text) = import_change(&module_scope(node), path);
+    TextEdit {
+        range: range(text_range, &file.index),
+        new_text,
+    }
+}
+
+/// One edit importing every path into its scope, given as the position of the scope among
+/// [`module_scopes`]. They're applied one after another, reparsing in between, so that imports
+/// going into the same `use` are merged with each other too.
+pub fn import_all_edit(file: &FileData, imports: &[(usize, Vec<String>)]) -> TextEdit {
+    let mut text = file.text.clone();
+    for (scope, path) in imports {
+        let root = SourceFile::parse(&text, file.edition).syntax_node();
+        let Some(scope) = module_scopes(&root).nth(*scope) else {
+            continue;
+        };
+        let (change_range, new_text) = import_change(&scope, path);
+        text.replace_range(std::ops::Range::<usize>::from(change_range), &new_text);
+    }
+
+    // Everything between the first and last changed bytes is replaced
+    let old = &file.text;
+    let mut start = old
+        .bytes()
+        .zip(text.bytes())
+        .take_while(|(a, b)| a == b)
+        .count();
+    while !old.is_char_boundary(start) {
+        start -= 1;
+    }
+    let common_end = old[start..]
+        .bytes()
+        .rev()
+        .zip(text[start..].bytes().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut old_end = old.len() - common_end;
+    while !old.is_char_boundary(old_end) {
+        old_end += 1;
+    }
+    let new_end = text.len() - (old.len() - old_end);
+
+    TextEdit {
+        range: range(
+            TextRange::new(TextSize::from(start as u32), TextSize::from(old_end as u32)),
+            &file.index,
+        ),
+        new_text: text[start..new_end].to_string(),
+    }
+}
+
+/// The source file and every inline module's item list under `root`, in order
+pub fn module_scopes(root: &SyntaxNode) -> impl Iterator<Item = SyntaxNode> {
+    root.descendants()
+        .filter(|node| matches!(node.kind(), SyntaxKind::SOURCE_FILE | SyntaxKind::ITEM_LIST))
+}
+
+/// The source file or inline module item list that `node` is in, where `use`s go
+pub fn module_scope(node: &SyntaxNode) -> SyntaxNode {
+    node.ancestors()
+        .find(|node| matches!(node.kind(), SyntaxKind::SOURCE_FILE | SyntaxKind::ITEM_LIST))
+        .unwrap_or_else(|| node.clone())
+}
+
+/// The text to replace to import `path` into `scope`
+pub fn import_change(scope: &SyntaxNode, path: &[String]) -> (TextRange, String) {
+    let uses = scope
+        .children()
+        .filter_map(ast::Use::cast)
+        .collect::<Vec<_>>();
+
+    // Merging into a `pub use` would re-export the name, and into a `#[cfg(..)]` one would only
+    // import it sometimes
+    let mergeable = uses
+        .iter()
+        .filter(|use_| use_.visibility().is_none() && use_.attrs().next().is_none())
+        .filter_map(|use_| use_.use_tree());
+    for tree in mergeable {
+        if let Some(change) = merge_into(&tree, path) {
+            return change;
+        }
+    }
+
+    new_use(scope, &uses, path)
+}
+
+/// Fit `path` into `tree`: appended to a list with the same prefix (`use a::{B};` becomes
+/// `use a::{B, C};`), or turning an import from the same module into one (`use a::B;` becomes
+/// `use a::{B, C};`)
+fn merge_into(tree: &ast::UseTree, path: &[String]) -> Option<(TextRange, String)> {
+    if tree.star_token().is_some() {
+        return None;
+    }
+    let tree_path = tree.path()?;
+    let segments = path_segments(&tree_path)?;
+
+    if let Some(list) = tree.use_tree_list() {
+        let rest = path.strip_prefix(segments.as_slice())?;
+        if rest.is_empty() {
+            return None;
+        }
+        if let Some(change) = list.use_trees().find_map(|child| merge_into(&child, rest)) {
+            return Some(change);
+        }
+
+        let text = rest.join("::");
+        return Some(match list.use_trees().last() {
+            Some(last) => (
+                TextRange::empty(last.syntax().text_range().end()),
+                format!(", {text}"),
+            ),
+            None => (
+                TextRange::empty(list.l_curly_token()?.text_range().end()),
+                text,
+            ),
+        });
+    }
+
+    // `use a::b;` importing `a::b::C` becomes `use a::b::{self, C};`
+    if let Some(rest) = path.strip_prefix(segments.as_slice())
+        && !rest.is_empty()
+        && tree.rename().is_none()
+    {
+        return Some((
+            tree.syntax().text_range(),
+            format!("{}::{{self, {}}}", segments.join("::"), rest.join("::")),
+        ));
+    }
+
+    let (_, module) = segments.split_last()?;
+    let (name, path_module) = path.split_last()?;
+    if module.is_empty() || module != path_module {
+        return None;
+    }
+
+    // Keep the last segment as written, along with any rename
+    let tree_text = tree.syntax().text().to_string();
+    let qualifier_end = tree_path.qualifier()?.syntax().text_range().end();
+    let leaf = tree_text[usize::from(qualifier_end - tree.syntax().text_range().start())..]
+        .trim_start()
+        .trim_start_matches("::")
+        .trim_start();
+    Some((
+        tree.syntax().text_range(),
+        format!("{}::{{{leaf}, {name}}}", module.join("::")),
+    ))
+}
+
+/// Add `use {path};` after the last `use` in `scope`, or after any `mod foo;` declarations at the
+/// top, or before the first item
+fn new_use(scope: &SyntaxNode, uses: &[ast::Use], path: &[String]) -> (TextRange, String) {
+    let text = format!("use {};", path.join("::"));
+    let items = scope
+        .children()
+        .filter(|node| ast::Item::can_cast(node.kind()))
+        .collect::<Vec<_>>();
+    let leading_declarations = items.iter().take_while(|item| {
+        item.kind() == SyntaxKind::EXTERN_CRATE
+            || ast::Module::cast((*item).clone()).is_some_and(|module| module.item_list().is_none())
+    });
+
+    if let Some(last) = uses
+        .last()
+        .map(|use_| use_.syntax())
+        .or(leading_declarations.last())
+    {
+        let indentation = indentation(last);
+        return (
+            TextRange::empty(last.text_range().end()),
+            format!("\n{indentation}{text}"),
+        );
+    }
+
+    if let Some(first) = items.first() {
+        let indentation = indentation(first);
+        return (
+            TextRange::empty(first.text_range().start()),
+            format!("{text}\n\n{indentation}"),
+        );
+    }
+
+    match ast::ItemList::cast(scope.clone()).and_then(|list| list.l_curly_token()) {
+        Some(l_curly) => (
+            TextRange::empty(l_curly.text_range().end()),
+            format!(" {text} "),
+        ),
+        None => (
+            TextRange::empty(scope.text_range().end()),
+            format!("{text}\n"),
+        ),
+    }
+}
+
+/// The whitespace `node`'s line starts with, when `node` starts the line
+fn indentation(node: &SyntaxNode) -> String {
+    node.prev_sibling_or_token()
+        .and_then(|element| element.into_token())
+        .filter(|token| token.kind() == SyntaxKind::WHITESPACE)
+        .and_then(|token| {
+            token
+                .text()
+                .rsplit_once('\n')
+                .map(|(_, indentation)| indentation.to_string())
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_analyzer_syntax::{Edition, SourceFile};
+
+    /// `text` with `path` imported at the top level
+    fn import(text: &str, path: &str) -> String {
+        let root = SourceFile::parse(text, Edition::Edition2021).syntax_node();
+        let path = path.split("::").map(String::from).collect::<Vec<_>>();
+        let (range, new_text) = import_change(&root, &path);
+        let mut text = text.to_string();
+        text.replace_range(std::ops::Range::<usize>::from(range), &new_text);
+        text
+    }
+
+    #[test]
+    fn merges_into_use_lists() {
+        assert_eq!(import("use a::{B};", "a::C"), "use a::{B, C};");
+        assert_eq!(import("use a::{};", "a::C"), "use a::{C};");
+        assert_eq!(
+            import("use a::{b::D, E};", "a::b::F"),
+            "use a::{b::{D, F}, E};"
+        );
+    }
+
+    #[test]
+    fn merges_imports_from_the_same_module() {
+        assert_eq!(import("use a::B;", "a::C"), "use a::{B, C};");
+        assert_eq!(import("use a::B as X;", "a::C"), "use a::{B as X, C};");
+        assert_eq!(import("use a::b;", "a::b::C"), "use a::b::{self, C};");
+    }
+
+    #[test]
+    fn adds_a_new_use_when_nothing_merges() {
+        assert_eq!(import("use a::*;", "a::C"), "use a::*;\nuse a::C;");
+        assert_eq!(import("pub use a::B;", "a::C"), "pub use a::B;\nuse a::C;");
+        assert_eq!(import("use x::B;", "a::C"), "use x::B;\nuse a::C;");
+        assert_eq!(
+            import("mod m;\nfn f() {}", "a::C"),
+            "mod m;\nuse a::C;\nfn f() {}"
+        );
+        assert_eq!(import("fn f() {}", "a::C"), "use a::C;\n\nfn f() {}");
+        assert_eq!(import("", "a::C"), "use a::C;\n");
+    }
+
+    #[test]
+    fn imports_into_inline_modules() {
+        let text = "mod m {\n    fn f() {}\n}";
+        let root = SourceFile::parse(text, Edition::Edition2021).syntax_node();
+        let scope = module_scopes(&root).nth(1).unwrap();
+        let (range, new_text) = import_change(&scope, &["a".to_string(), "C".to_string()]);
+        assert_eq!(range, TextRange::empty(12.into()));
+        assert_eq!(new_text, "use a::C;\n\n    ");
+    }
+}
@@ -1,12 +1,22 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::Path;
 
 use lsp_types::{
-    CodeAction, CodeActionKind, CodeActionOrCommand, CodeActionParams, Position, Range, TextEdit,
-    WorkspaceEdit,
+    CodeAction, CodeActionKind, CodeActionOrCommand, CodeActionParams, CreateFile,
+    CreateFileOptions, DocumentChangeOperation, DocumentChanges, OneOf,
+    OptionalVersionedTextDocumentIdentifier, Position, Range, ResourceOp, TextDocumentEdit,
+    TextEdit, Url, WorkspaceEdit,
 };
-use snafu::{OptionExt, ResultExt, Whatever};
+use snafu::{ResultExt, Whatever};
 
+use crate::auto_import::{
+    import_all_edit, import_candidates, import_edit, is_prelude_name, module_scope, module_scopes,
+    unresolved_name,
+};
+use crate::database::module::get_module_name;
+use crate::database::resolve::module_at;
 use crate::database::{Database, FileUrl};
+use crate::utils::token_at_offset;
 
 /// Add `[pub] mod {name};` to parent file
 pub fn add_mod_to_parent(
@@ -16,10 +26,8 @@ pub fn add_mod_to_parent(
 ) -> Result<(), Whatever> {
     let file = FileUrl::from_url(params.text_document.uri.clone())?;
 
-    let file_name = file.path().file_stem().expect("`FileUrl` had no filename");
-    let file_name_str = file_name
-        .to_str()
-        .whatever_context("filename was not UTF8")?;
+    // `mod.rs` files are named after their directory
+    let module_name = get_module_name(&file);
     db.load_file(&file)
         .with_whatever_context(|_| format!("failed to load file `{}`", file.url()))?;
     if let Some(parent_url) = &db
@@ -30,7 +38,7 @@ pub fn add_mod_to_parent(
     {
         let parent = db
             .files
-            .get(&parent_url)
+            .get(parent_url)
             .expect("found parent URL but the parent file was not present in database");
 
         let last_include_range = parent
@@ -42,17 +50,15 @@ pub fn add_mod_to_parent(
         let parent_has_this_module = parent
             .modules
             .iter()
-            .any(|module| &module.name[..] == file_name);
+            .any(|module| module.name == module_name);
         if !parent_has_this_module {
             actions.push(insert_mod_private(
-                file_name,
-                file_name_str,
+                &module_name,
                 last_include_range,
                 parent_url,
             ));
             actions.push(insert_mod_public(
-                file_name,
-                file_name_str,
+                &module_name,
                 last_include_range,
                 parent_url,
             ));
@@ -62,25 +68,254 @@ pub fn add_mod_to_parent(
     Ok(())
 }
 
+/// Offer to import the unresolved name under the cursor from everywhere it's defined, closest first
+pub fn import_name(
+    db: &mut Database,
+    params: &CodeActionParams,
+    actions: &mut Vec<CodeActionOrCommand>,
+) -> Result<(), Whatever> {
+    let file = FileUrl::from_url(params.text_document.uri.clone())?;
+    db.load_file(&file)
+        .with_whatever_context(|_| format!("failed to load file `{}`", file.url()))?;
+    let text_doc = db
+        .files
+        .get(&file)
+        .expect("successfully loaded file but it was not present in database");
+
+    let Some(token) = text_doc
+        .index
+        .offset(params.range.start)
+        .and_then(|offset| token_at_offset(&text_doc.ast.syntax_node(), offset))
+    else {
+        return Ok(());
+    };
+    let Some(name) = unresolved_name(db, text_doc, &token) else {
+        return Ok(());
+    };
+    let node = token.parent().expect("token had no parent");
+    let module = module_at(&text_doc.module_path, &node);
+
+    for candidate in import_candidates(db, &module, |candidate| candidate == name) {
+        let edit = import_edit(text_doc, &node, &candidate.path);
+        actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+            title: format!("Import `{}`", candidate.path.join("::")),
+            kind: Some(CodeActionKind::QUICKFIX),
+            edit: Some(WorkspaceEdit {
+                changes: Some(HashMap::from_iter([(file.url().clone(), vec![edit])])),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }));
+    }
+
+    Ok(())
+}
+
+/// Import every unresolved name in the file that has exactly one candidate. Ambiguous names,
+/// including those shadowing the prelude, are left for [`import_name`] and listed in the title, so
+/// they aren't missed. Walking the whole file is too slow to do on every cursor move, so it's only
+/// done when the client asks for source actions.
+pub fn import_all(
+    db: &mut Database,
+    params: &CodeActionParams,
+    actions: &mut Vec<CodeActionOrCommand>,
+) -> Result<(), Whatever> {
+    let wants_source = params.context.only.as_ref().is_none_or(|only| {
+        only.iter()
+            .any(|kind| kind.as_str().is_empty() || kind == &CodeActionKind::SOURCE)
+    });
+    if !wants_source {
+        return Ok(());
+    }
+
+    let file = FileUrl::from_url(params.text_document.uri.clone())?;
+    db.load_file(&file)
+        .with_whatever_context(|_| format!("failed to load file `{}`", file.url()))?;
+    let text_doc = db
+        .files
+        .get(&file)
+        .expect("successfully loaded file but it was not present in database");
+
+    let root = text_doc.ast.syntax_node();
+    let scopes = module_scopes(&root).collect::<Vec<_>>();
+    let mut seen = HashSet::new();
+    let mut imports = vec![];
+    let mut ambiguous = BTreeSet::new();
+    for token in root
+        .descendants_with_tokens()
+        .filter_map(|element| element.into_token())
+    {
+        let Some(name) = unresolved_name(db, text_doc, &token) else {
+            continue;
+        };
+        let node = token.parent().expect("token had no parent");
+        let scope = scopes
+            .iter()
+            .position(|scope| scope == &module_scope(&node))
+            .expect("token was outside of every module scope");
+        if !seen.insert((scope, name.clone())) {
+            continue;
+        }
+
+        let module = module_at(&text_doc.module_path, &node);
+        match import_candidates(db, &module, |candidate| candidate == name).as_slice() {
+            [] => {}
+            [candidate] if !is_prelude_name(&name) => imports.push((scope, candidate.path.clone())),
+            _ => {
+                ambiguous.insert(name);
+            }
+        }
+    }
+    if imports.is_empty() {
+        return Ok(());
+    }
+
+    imports.sort();
+    let title = match ambiguous.is_empty() {
+        true => "Import all unresolved names".to_string(),
+        false => format!(
+            "Import all unresolved names (skipping ambiguous {})",
+            ambiguous
+                .iter()
+                .map(|name| format!("`{name}`"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+        title,
+        kind: Some(CodeActionKind::SOURCE),
+        edit: Some(WorkspaceEdit {
+            changes: Some(HashMap::from_iter([(
+                file.url().clone(),
+                vec![import_all_edit(text_doc, &imports)],
+            )])),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }));
+
+    Ok(())
+}
+
+/// Offer to create the missing file of the `mod foo;` declaration under the cursor, either empty
+/// or starting with a module doc comment
+pub fn create_module_file(
+    db: &mut Database,
+    params: &CodeActionParams,
+    actions: &mut Vec<CodeActionOrCommand>,
+) -> Result<(), Whatever> {
+    if !db.supports_create_file() {
+        return Ok(());
+    }
+
+    let file = FileUrl::from_url(params.text_document.uri.clone())?;
+    db.load_file(&file)
+        .with_whatever_context(|_| format!("failed to load file `{}`", file.url()))?;
+    let text_doc = db
+        .files
+        .get(&file)
+        .expect("successfully loaded file but it was not present in database");
+
+    let Some(include) = text_doc.modules.iter().find(|include| {
+        include.range.start <= params.range.start && params.range.start <= include.range.end
+    }) else {
+        return Ok(());
+    };
+    // Both `foo.rs` and `foo/mod.rs` are fine for a plain declaration, but only if there's neither
+    let missing = match include.path_attribute {
+        true => include
+            .paths
+            .iter()
+            .filter(|path| !path.is_file())
+            .collect::<Vec<_>>(),
+        false if include.paths.iter().any(|path| path.is_file()) => vec![],
+        false => include.paths.iter().collect(),
+    };
+    let diagnostics = params
+        .context
+        .diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.range == include.range)
+        .cloned()
+        .collect::<Vec<_>>();
+
+    let dir = file.path().parent().unwrap_or(Path::new("/"));
+    for path in missing {
+        let Ok(url) = Url::from_file_path(path) else {
+            continue;
+        };
+        let display = path.strip_prefix(dir).unwrap_or(path).display();
+        let header = format!("//! The `{}` module.\n", include.name);
+        for (title, contents) in [
+            (format!("Create `{display}`"), None),
+            (
+                format!("Create `{display}` with a doc comment"),
+                Some(header),
+            ),
+        ] {
+            actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                title,
+                kind: Some(CodeActionKind::QUICKFIX),
+                diagnostics: Some(diagnostics.clone())
+                    .filter(|diagnostics| !diagnostics.is_empty()),
+                edit: Some(create_file(&url, contents)),
+                ..Default::default()
+            }));
+        }
+    }
+
+    Ok(())
+}
+
+/// Create the file at `url` if there isn't one, filling it with `contents`
+fn create_file(url: &Url, contents: Option<String>) -> WorkspaceEdit {
+    let mut operations = vec![DocumentChangeOperation::Op(ResourceOp::Create(
+        CreateFile {
+            uri: url.clone(),
+            options: Some(CreateFileOptions {
+                overwrite: Some(false),
+                ignore_if_exists: Some(true),
+            }),
+            annotation_id: None,
+        },
+    ))];
+    if let Some(contents) = contents {
+        operations.push(DocumentChangeOperation::Edit(TextDocumentEdit {
+            text_document: OptionalVersionedTextDocumentIdentifier {
+                uri: url.clone(),
+                version: None,
+            },
+            edits: vec![OneOf::Left(TextEdit {
+                range: Range::default(),
+                new_text: contents,
+            })],
+        }));
+    }
+
+    WorkspaceEdit {
+        document_changes: Some(DocumentChanges::Operations(operations)),
+        ..Default::default()
+    }
+}
+
 fn insert_mod_private(
-    file_name: &std::ffi::OsStr,
-    file_name_str: &str,
+    module_name: &str,
     last_include_range: Range,
     parent_url: &FileUrl,
 ) -> CodeActionOrCommand {
-    let title = format!("Insert `mod {};`", file_name.to_string_lossy());
-    let new_text = format!("\nmod {file_name_str};");
+    let title = format!("Insert `mod {module_name};`");
+    let new_text = format!("\nmod {module_name};");
     insert_mod(title, new_text, last_include_range, parent_url)
 }
 
 fn insert_mod_public(
-    file_name: &std::ffi::OsStr,
-    file_name_str: &str,
+    module_name: &str,
     last_include_range: Range,
     parent_url: &FileUrl,
 ) -> CodeActionOrCommand {
-    let title = format!("Insert `pub mod {};`", file_name.to_string_lossy());
-    let new_text = format!("\npub mod {file_name_str};");
+    let title = format!("Insert `pub mod {module_name};`");
+    let new_text = format!("\npub mod {module_name};");
     insert_mod(title, new_text, last_include_range, parent_url)
 }
 
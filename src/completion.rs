@@ -0,0 +1,697 @@
+use std::collections::HashSet;
+
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionItemLabelDetails, CompletionList,
+    CompletionParams,
+};
+use rust_analyzer_syntax::ast;
+use rust_analyzer_syntax::{AstNode, SyntaxKind, SyntaxNode, SyntaxToken, TextSize};
+use snafu::{OptionExt, ResultExt, Whatever};
+
+use crate::auto_import::{import_candidates, import_edit};
+use crate::database::resolve::{
+    Resolution, is_visible, module_at, module_imports, path_segments, replace_self_type,
+    resolve_path, use_tree_prefix,
+};
+use crate::database::scope::visible_locals;
+use crate::database::symbol_index::fuzzy_score;
+use crate::database::{
+    ChildKind, Database, FileData, FileUrl, ItemData, ItemKind, ItemPath, ModulePath,
+};
+use crate::hover::{node_at_range, signature};
+
+const ITEM_KEYWORDS: &[&str] = &[
+    "fn",
+    "struct",
+    "enum",
+    "union",
+    "trait",
+    "impl",
+    "mod",
+    "use",
+    "const",
+    "static",
+    "type",
+    "pub",
+    "unsafe",
+    "extern",
+    "async",
+    "macro_rules!",
+];
+const ASSOCIATED_ITEM_KEYWORDS: &[&str] = &["fn", "const", "type", "pub", "unsafe", "async"];
+const EXPRESSION_KEYWORDS: &[&str] = &[
+    "if", "match", "loop", "while", "for", "unsafe", "async", "move", "true", "false", "crate",
+    "super",
+];
+const TYPE_KEYWORDS: &[&str] = &[
+    "dyn", "impl", "fn", "unsafe", "extern", "crate", "super", "self",
+];
+const PATTERN_KEYWORDS: &[&str] = &["mut", "ref", "true", "false"];
+const USE_KEYWORDS: &[&str] = &["crate", "self", "super"];
+const PRIMITIVE_TYPES: &[&str] = &[
+    "bool", "char", "str", "i8", "i16", "i32", "i64", "i128", "isize", "u8", "u16", "u32", "u64",
+    "u128", "usize", "f32", "f64",
+];
+
+/// How many items that aren't in scope are offered at most, closest first
+const MAX_IMPORT_COMPLETIONS: usize = 64;
+
+/// What can be written at the cursor, worked out from the syntax around it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Context {
+    /// An expression; `statement` if it's also the start of a statement
+    Expression {
+        statement: bool,
+    },
+    Type,
+    Pattern,
+    /// A path in a `use` declaration
+    Use,
+    /// Where an item is declared; `associated` inside an `impl` or `trait` block
+    Item {
+        associated: bool,
+    },
+}
+
+impl Context {
+    /// Whether an item of `kind` can be named here, either by itself or as the start of a longer
+    /// path
+    fn allows(self, kind: ItemKind) -> bool {
+        match self {
+            Context::Expression { .. } | Context::Use => true,
+            Context::Type => !matches!(
+                kind,
+                ItemKind::Fn | ItemKind::Const | ItemKind::Static | ItemKind::Macro
+            ),
+            Context::Pattern => matches!(
+                kind,
+                ItemKind::Struct
+                    | ItemKind::Enum
+                    | ItemKind::Union
+                    | ItemKind::TypeAlias
+                    | ItemKind::Const
+                    | ItemKind::ExternCrate
+            ),
+            Context::Item { .. } => kind == ItemKind::Macro,
+        }
+    }
+}
+
+/// Completions collected so far. Names that are already there are skipped, so whatever is added
+/// first (locals before items) wins.
+#[derive(Default)]
+struct Completions {
+    items: Vec<CompletionItem>,
+    labels: HashSet<String>,
+}
+
+impl Completions {
+    fn push(&mut self, item: CompletionItem) {
+        if self.labels.insert(item.label.clone()) {
+            self.items.push(item);
+        }
+    }
+
+    fn push_simple(&mut self, label: &str, kind: CompletionItemKind) {
+        self.push(CompletionItem {
+            label: label.to_string(),
+            kind: Some(kind),
+            ..Default::default()
+        });
+    }
+}
+
+/// Completions at the cursor: the members of whatever comes before a `::`, or otherwise the names
+/// in scope, the keywords that fit where the cursor is and items that could be imported. Apart from
+/// the importable items, everything is returned regardless of what's been typed so far, since
+/// clients filter completions themselves.
+pub fn completions(
+    db: &mut Database,
+    params: &CompletionParams,
+) -> Result<CompletionList, Whatever> {
+    let position = &params.text_document_position;
+    let file = FileUrl::from_url(position.text_document.uri.clone())?;
+    db.load_file(&file)
+        .with_whatever_context(|_| format!("failed to load file `{}`", file.url()))?;
+
+    let db = &*db;
+    let file = db
+        .files
+        .get(&file)
+        .expect("successfully loaded file but it was not present in database");
+    let offset = file
+        .index
+        .offset(position.position)
+        .whatever_context("position was outside of the file")?;
+
+    // The start of the identifier being typed, if any
+    let before = file
+        .text
+        .get(..usize::from(offset))
+        .whatever_context("position was outside of the file")?;
+    let name_start = before.trim_end_matches(is_identifier_char).len();
+    let start = TextSize::from(name_start as u32);
+
+    let root = file.ast.syntax_node();
+    let token = match start == offset {
+        true => root.token_at_offset(offset).left_biased(),
+        false => root.token_at_offset(start).right_biased(),
+    }
+    .whatever_context("no token at position")?;
+    let Some(context) = context_at(&token, previous_token(&root, start).as_ref(), start) else {
+        return Ok(CompletionList::default());
+    };
+
+    let node = token.parent().whatever_context("token had no parent")?;
+    let module = module_at(&file.module_path, &node);
+    let mut completions = Completions::default();
+    let mut is_incomplete = false;
+    match qualifier(&before[..name_start], &node, context) {
+        Some(qualifier) => {
+            path_completions(db, &module, &node, qualifier, context, &mut completions)
+        }
+        None => {
+            if matches!(context, Context::Expression { .. } | Context::Type) {
+                local_completions(&node, start, context, &mut completions);
+            }
+            module_completions(db, &module, &module, context, &mut completions);
+            keyword_completions(&node, context, &mut completions);
+
+            let typed = &before[name_start..];
+            if !typed.is_empty()
+                && matches!(
+                    context,
+                    Context::Expression { .. } | Context::Type | Context::Pattern
+                )
+            {
+                import_completions(db, file, &node, &module, typed, context, &mut completions);
+                // The importable items depend on what's been typed, so ask again as it changes
+                is_incomplete = true;
+            }
+        }
+    }
+
+    Ok(CompletionList {
+        is_incomplete,
+        items: completions.items,
+    })
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// The last token before `offset` that isn't whitespace or a comment
+fn previous_token(root: &SyntaxNode, offset: TextSize) -> Option<SyntaxToken> {
+    let mut token = root.token_at_offset(offset).left_biased()?;
+    while token.kind().is_trivia() {
+        token = token.prev_token()?;
+    }
+    Some(token)
+}
+
+/// Work out the context from the token being typed (or the one just before the cursor, if nothing
+/// has been typed yet). Returns `None` where nothing existing can be named, like after `fn`.
+///
+/// Incomplete code often parses without the node the cursor will end up in (`let x: ` has no type
+/// yet), so the token before the cursor is checked first.
+fn context_at(
+    token: &SyntaxToken,
+    previous: Option<&SyntaxToken>,
+    start: TextSize,
+) -> Option<Context> {
+    if let Some(previous) = previous {
+        let parent = previous.parent().map(|parent| parent.kind());
+        match previous.kind() {
+            SyntaxKind::LET_KW
+            | SyntaxKind::FN_KW
+            | SyntaxKind::STRUCT_KW
+            | SyntaxKind::ENUM_KW
+            | SyntaxKind::TRAIT_KW
+            | SyntaxKind::MOD_KW
+            | SyntaxKind::TYPE_KW => return None,
+            SyntaxKind::USE_KW => return Some(Context::Use),
+            SyntaxKind::THIN_ARROW | SyntaxKind::DYN_KW | SyntaxKind::AS_KW => {
+                return Some(Context::Type);
+            }
+            SyntaxKind::COLON
+                if matches!(
+                    parent,
+                    Some(
+                        SyntaxKind::LET_STMT
+                            | SyntaxKind::PARAM
+                            | SyntaxKind::SELF_PARAM
+                            | SyntaxKind::RECORD_FIELD
+                            | SyntaxKind::CONST
+                            | SyntaxKind::STATIC
+                    )
+                ) =>
+            {
+                return Some(Context::Type);
+            }
+            SyntaxKind::L_ANGLE | SyntaxKind::COMMA
+                if parent == Some(SyntaxKind::GENERIC_ARG_LIST) =>
+            {
+                return Some(Context::Type);
+            }
+            SyntaxKind::EQ
+                if matches!(
+                    parent,
+                    Some(SyntaxKind::LET_STMT | SyntaxKind::CONST | SyntaxKind::STATIC)
+                ) =>
+            {
+                return Some(Context::Expression { statement: false });
+            }
+            _ => {}
+        }
+    }
+
+    for node in token.parent_ancestors() {
+        let kind = node.kind();
+        match kind {
+            SyntaxKind::USE | SyntaxKind::USE_TREE => return Some(Context::Use),
+            SyntaxKind::PATH_TYPE => return Some(Context::Type),
+            SyntaxKind::STMT_LIST => return Some(Context::Expression { statement: true }),
+            SyntaxKind::SOURCE_FILE | SyntaxKind::ITEM_LIST => {
+                return Some(Context::Item { associated: false });
+            }
+            SyntaxKind::ASSOC_ITEM_LIST => return Some(Context::Item { associated: true }),
+            _ if ast::Pat::can_cast(kind) => return Some(Context::Pattern),
+            _ if ast::Expr::can_cast(kind) => {
+                let statement = node.text_range().start() == start
+                    && node.parent().is_some_and(|parent| {
+                        matches!(parent.kind(), SyntaxKind::STMT_LIST | SyntaxKind::EXPR_STMT)
+                    });
+                return Some(Context::Expression { statement });
+            }
+            // Words typed where an item goes parse as the start of a macro call
+            SyntaxKind::MACRO_CALL => {}
+            _ if ast::Item::can_cast(kind) => return None,
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// The path before the `::` that the cursor is right after, like `["crate", "net"]` for
+/// `crate::net::Cl`, or `None` if there isn't one. Inside a use tree list like
+/// `use crate::{net::Cl`, the paths of the enclosing trees come first.
+fn qualifier(before: &str, node: &SyntaxNode, context: Context) -> Option<Vec<String>> {
+    let mut segments = vec![];
+    let mut rest = before.trim_end();
+    while let Some(stripped) = rest.strip_suffix("::") {
+        let stripped = stripped.trim_end();
+        let segment_start = stripped.trim_end_matches(is_identifier_char).len();
+        // Turbofish generics and the like can't be resolved
+        if segment_start == stripped.len() {
+            return None;
+        }
+
+        segments.push(stripped[segment_start..].to_string());
+        rest = stripped[..segment_start].trim_end();
+    }
+    segments.reverse();
+
+    if context == Context::Use && (rest.ends_with('{') || rest.ends_with(',')) {
+        let tree = node
+            .ancestors()
+            .find_map(ast::UseTreeList::cast)?
+            .syntax()
+            .parent()
+            .and_then(ast::UseTree::cast)?;
+        let mut prefix = use_tree_prefix(&tree)?;
+        prefix.extend(path_segments(&tree.path()?)?);
+        prefix.extend(segments);
+        return Some(prefix);
+    }
+
+    (!segments.is_empty()).then_some(segments)
+}
+
+/// The members of the module or type `qualifier` resolves to
+fn path_completions(
+    db: &Database,
+    module: &ModulePath,
+    node: &SyntaxNode,
+    qualifier: Vec<String>,
+    context: Context,
+    completions: &mut Completions,
+) {
+    let Some(qualifier) = replace_self_type(node, qualifier) else {
+        return;
+    };
+
+    match resolve_path(db, module, &qualifier) {
+        Some(Resolution::Module(target)) => {
+            module_completions(db, &target, module, context, completions);
+        }
+        Some(Resolution::Item(owner)) => {
+            member_completions(db, &owner, module, context, completions);
+        }
+        _ => {}
+    }
+}
+
+/// The local bindings and generic parameters in scope; only type parameters in type context
+fn local_completions(
+    node: &SyntaxNode,
+    offset: TextSize,
+    context: Context,
+    completions: &mut Completions,
+) {
+    for binding in visible_locals(node, offset) {
+        let declaration = binding
+            .parent()
+            .and_then(|name| name.parent())
+            .map(|declaration| declaration.kind());
+        let kind = match declaration {
+            Some(SyntaxKind::TYPE_PARAM) => CompletionItemKind::TYPE_PARAMETER,
+            Some(SyntaxKind::CONST_PARAM) => CompletionItemKind::CONSTANT,
+            _ => CompletionItemKind::VARIABLE,
+        };
+
+        if context != Context::Type || kind == CompletionItemKind::TYPE_PARAMETER {
+            completions.push_simple(binding.text(), kind);
+        }
+    }
+}
+
+/// Everything in `target` that can be named from `from`: its items, child modules and imports,
+/// along with whatever its glob imports bring in
+fn module_completions(
+    db: &Database,
+    target: &ModulePath,
+    from: &ModulePath,
+    context: Context,
+    completions: &mut Completions,
+) {
+    module_member_completions(db, target, from, context, completions);
+
+    for import in module_imports(db, target) {
+        if !import.glob || !is_visible(&import.visibility, target, from) {
+            continue;
+        }
+
+        // Globs aren't followed any further, since they can form cycles
+        match resolve_path(db, target, &import.path) {
+            Some(Resolution::Module(globbed)) if &globbed != target => {
+                module_member_completions(db, &globbed, from, context, completions);
+            }
+            Some(Resolution::Item(owner)) => {
+                variant_completions(db, &owner, context, completions);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The items, child modules and named imports of `target` that can be named from `from`
+fn module_member_completions(
+    db: &Database,
+    target: &ModulePath,
+    from: &ModulePath,
+    context: Context,
+    completions: &mut Completions,
+) {
+    let module = db.modules.get(target);
+    for name in module.into_iter().flat_map(|module| &module.items) {
+        let path = ItemPath {
+            module: target.clone(),
+            name: name.clone(),
+        };
+        let Some(data) = db.items.get(&path).and_then(|defs| defs.first()) else {
+            continue;
+        };
+        if is_visible(&data.visibility, target, from) && context.allows(data.kind) {
+            completions.push(item_completion(db, name, data));
+        }
+    }
+
+    let allows_modules = !matches!(context, Context::Item { .. });
+    if allows_modules && let Some(module) = module {
+        for child in &module.children {
+            completions.push_simple(child, CompletionItemKind::MODULE);
+        }
+    }
+
+    for import in module_imports(db, target) {
+        let Some(name) = &import.name else {
+            continue;
+        };
+        if !is_visible(&import.visibility, target, from) {
+            continue;
+        }
+
+        match resolve_path(db, target, &import.path) {
+            Some(Resolution::Module(_)) if allows_modules => {
+                completions.push_simple(name, CompletionItemKind::MODULE);
+            }
+            Some(Resolution::Item(item)) => {
+                let Some(data) = db.items.get(&item).and_then(|defs| defs.first()) else {
+                    continue;
+                };
+                if context.allows(data.kind) {
+                    completions.push(item_completion(db, name, data));
+                }
+            }
+            Some(Resolution::Child { .. }) if context != Context::Type => {
+                completions.push_simple(name, CompletionItemKind::ENUM_MEMBER);
+            }
+            // Imports from outside the workspace can't be checked against the context
+            None => completions.push(CompletionItem {
+                label: name.clone(),
+                ..Default::default()
+            }),
+            _ => {}
+        }
+    }
+}
+
+/// The variants and associated items of `owner` that fit the context
+fn member_completions(
+    db: &Database,
+    owner: &ItemPath,
+    from: &ModulePath,
+    context: Context,
+    completions: &mut Completions,
+) {
+    variant_completions(db, owner, context, completions);
+    // Associated items can't be imported
+    if context == Context::Use {
+        return;
+    }
+
+    for item in db.associated_items.get(&owner.name).into_iter().flatten() {
+        let fits = match context {
+            Context::Type => item.kind == ItemKind::TypeAlias,
+            Context::Pattern => item.kind == ItemKind::Const,
+            _ => true,
+        };
+        if !fits
+            || resolve_path(db, &item.module, &item.owner) != Some(Resolution::Item(owner.clone()))
+        {
+            continue;
+        }
+
+        let node = db
+            .files
+            .get(&item.file_path)
+            .and_then(|file| node_at_range(file, item.range));
+        // Trait items are as visible as the trait itself
+        let is_trait_item = node
+            .as_ref()
+            .and_then(|node| node.parent()?.parent())
+            .is_some_and(|block| match ast::Impl::cast(block.clone()) {
+                Some(impl_) => impl_.trait_().is_some(),
+                None => block.kind() == SyntaxKind::TRAIT,
+            });
+        if !is_trait_item && !is_visible(&item.visibility, &item.module, from) {
+            continue;
+        }
+
+        let has_self = node
+            .clone()
+            .and_then(ast::Fn::cast)
+            .and_then(|function| function.param_list())
+            .is_some_and(|params| params.self_param().is_some());
+        let kind = match has_self {
+            true => CompletionItemKind::METHOD,
+            false => item.kind.completion_kind(),
+        };
+        completions.push(CompletionItem {
+            label: item.name.clone(),
+            kind: Some(kind),
+            detail: node.map(|node| signature(&node)),
+            ..Default::default()
+        });
+    }
+}
+
+/// The variants of `owner`, if it's an enum. Variants aren't types, so there are none in type
+/// context.
+fn variant_completions(
+    db: &Database,
+    owner: &ItemPath,
+    context: Context,
+    completions: &mut Completions,
+) {
+    if context == Context::Type {
+        return;
+    }
+
+    for child in db
+        .items
+        .get(owner)
+        .into_iter()
+        .flatten()
+        .flat_map(|data| &data.children)
+        .filter(|child| child.kind == ChildKind::Variant)
+    {
+        completions.push_simple(&child.name, CompletionItemKind::ENUM_MEMBER);
+    }
+}
+
+/// Items from elsewhere whose names fuzzily match what's been typed, which get imported when
+/// picked. There can be a lot of them, so only the closest few are offered.
+fn import_completions(
+    db: &Database,
+    file: &FileData,
+    node: &SyntaxNode,
+    module: &ModulePath,
+    typed: &str,
+    context: Context,
+    completions: &mut Completions,
+) {
+    // Macros are labelled with their `!`, but candidates are bare names
+    let in_scope = completions
+        .labels
+        .iter()
+        .map(|label| label.trim_end_matches('!').to_string())
+        .collect::<HashSet<_>>();
+    let candidates = import_candidates(db, module, |name| {
+        !in_scope.contains(name) && fuzzy_score(typed, name).is_some()
+    });
+
+    for candidate in candidates
+        .into_iter()
+        .filter(|candidate| candidate.item.is_none_or(|data| context.allows(data.kind)))
+        .take(MAX_IMPORT_COMPLETIONS)
+    {
+        let (name, parent) = candidate
+            .path
+            .split_last()
+            .expect("import candidate had an empty path");
+        let mut item = match candidate.item {
+            Some(data) => item_completion(db, name, data),
+            None => CompletionItem {
+                label: name.clone(),
+                kind: Some(CompletionItemKind::MODULE),
+                ..Default::default()
+            },
+        };
+        item.label_details = Some(CompletionItemLabelDetails {
+            detail: None,
+            description: Some(parent.join("::")),
+        });
+        item.additional_text_edits = Some(vec![import_edit(file, node, &candidate.path)]);
+
+        // Names from different modules are all worth offering, so these skip deduplication
+        completions.items.push(item);
+    }
+}
+
+fn item_completion(db: &Database, name: &str, data: &ItemData) -> CompletionItem {
+    let detail = db
+        .files
+        .get(&data.file_path)
+        .and_then(|file| node_at_range(file, data.range))
+        .map(|node| signature(&node));
+    let label = match data.kind {
+        ItemKind::Macro => format!("{name}!"),
+        _ => name.to_string(),
+    };
+
+    CompletionItem {
+        label,
+        kind: Some(data.kind.completion_kind()),
+        detail,
+        ..Default::default()
+    }
+}
+
+/// The keywords (and primitive types) that can be written in `context`, where `node` is. Some
+/// depend on what's around, like `break` only being allowed inside loops.
+fn keyword_completions(node: &SyntaxNode, context: Context, completions: &mut Completions) {
+    let mut keywords = match context {
+        Context::Expression { .. } => EXPRESSION_KEYWORDS.to_vec(),
+        Context::Type => TYPE_KEYWORDS.to_vec(),
+        Context::Pattern => PATTERN_KEYWORDS.to_vec(),
+        Context::Use => USE_KEYWORDS.to_vec(),
+        Context::Item { associated: false } => ITEM_KEYWORDS.to_vec(),
+        Context::Item { associated: true } => ASSOCIATED_ITEM_KEYWORDS.to_vec(),
+    };
+
+    if let Context::Expression { statement } = context {
+        if statement {
+            keywords.push("let");
+        }
+
+        let body_boundary = |node: &SyntaxNode| {
+            matches!(
+                node.kind(),
+                SyntaxKind::FN | SyntaxKind::CLOSURE_EXPR | SyntaxKind::CONST | SyntaxKind::STATIC
+            )
+        };
+        if node.ancestors().find(body_boundary).is_some_and(|body| {
+            body.kind() != SyntaxKind::CONST && body.kind() != SyntaxKind::STATIC
+        }) {
+            keywords.push("return");
+        }
+
+        let in_loop = node
+            .ancestors()
+            .take_while(|node| !body_boundary(node))
+            .any(|node| {
+                matches!(
+                    node.kind(),
+                    SyntaxKind::LOOP_EXPR | SyntaxKind::WHILE_EXPR | SyntaxKind::FOR_EXPR
+                )
+            });
+        if in_loop {
+            keywords.extend(["break", "continue"]);
+        }
+
+        let has_self = node
+            .ancestors()
+            .find_map(ast::Fn::cast)
+            .and_then(|function| function.param_list())
+            .is_some_and(|params| params.self_param().is_some());
+        if has_self {
+            keywords.push("self");
+        }
+    }
+
+    // `Self` is the type of the enclosing impl or trait, or the type being defined
+    let has_self_type = node.ancestors().any(|node| {
+        matches!(node.kind(), SyntaxKind::IMPL | SyntaxKind::TRAIT)
+            || (context == Context::Type && ast::Adt::can_cast(node.kind()))
+    });
+    if has_self_type
+        && matches!(
+            context,
+            Context::Expression { .. } | Context::Type | Context::Pattern
+        )
+    {
+        keywords.push("Self");
+    }
+
+    for keyword in keywords {
+        completions.push_simple(keyword, CompletionItemKind::KEYWORD);
+    }
+    if context == Context::Type {
+        for primitive in PRIMITIVE_TYPES {
+            completions.push_simple(primitive, CompletionItemKind::STRUCT);
+        }
+    }
+}
@@ -1,23 +1,37 @@
 pub mod ast_scan;
+pub mod doc_links;
 pub mod file;
+pub mod incremental;
+pub mod manifest;
 pub mod module;
+pub mod references;
+pub mod resolve;
+pub mod scope;
+pub mod symbol_index;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::panic::Location;
 use std::path::{Path, PathBuf};
 
-use line_index::LineIndex;
+use line_index::WideEncoding;
 use lsp_server::{Connection, Message, Notification};
-use lsp_types::{InitializeParams, MessageType, Range, Url, WorkspaceFolder};
-use rust_analyzer_syntax::{Edition, Parse, SourceFile};
+use lsp_types::{
+    CompletionItemKind, InitializeParams, MessageType, Range, ResourceOperationKind,
+    SemanticTokens, SymbolKind, TextDocumentContentChangeEvent, Url, WorkspaceFolder,
+};
+use rust_analyzer_syntax::{Edition, Parse, SourceFile, SyntaxKind, TextRange, TextSize};
 use serde_json::json;
 use snafu::{OptionExt, ResultExt, Whatever};
 
 use crate::database::ast_scan::scan_ast;
-use crate::database::file::{get_file_diagnostics, post_diagnostics};
+use crate::database::file::{file_diagnostics, post_diagnostics};
+use crate::database::incremental::rescan_edited;
+use crate::database::manifest::{Package, package_of};
 use crate::database::module::scan_file_modules;
+use crate::database::symbol_index::{SymbolId, SymbolIndex};
+use crate::utils::{PositionEncoding, PositionIndex};
 
 /// A canonicalized path with its URL generated and saved ahead of time, since almost every usage
 /// of a path will at some point require producing a URL from it (for this LSP).
@@ -85,10 +99,26 @@ pub struct Database {
     pub connection: Connection,
     pub initialize_params: InitializeParams,
     pub workspace_folders: Vec<WorkspaceFolder>,
+    pub position_encoding: PositionEncoding,
     pub files: HashMap<FileUrl, FileData>,
     pub modules: HashMap<ModulePath, ModuleData>,
-    pub type_defs: HashMap<ItemPath, TypeDefData>,
-    pub function_defs: HashMap<ItemPath, FunctionDefData>,
+    /// Several items can share a path, e.g. when they're declared behind different `#[cfg]`s
+    pub items: HashMap<ItemPath, Vec<ItemData>>,
+    /// Items declared in `impl` and `trait` blocks, keyed by the name of their owner (the impl's
+    /// self type or the trait). Owners are only resolved on lookup, since the owning type may live
+    /// in a file that hasn't been scanned yet.
+    pub associated_items: HashMap<String, Vec<AssociatedItemData>>,
+    /// Every identifier used (rather than declared) in the workspace, keyed by its text
+    pub name_refs: HashMap<String, Vec<NameRefData>>,
+    /// Every item and module in the workspace, for `workspace/symbol`
+    pub symbols: SymbolIndex,
+    /// Every package found in a `Cargo.toml` in the workspace
+    pub packages: Vec<Package>,
+    /// The files `mod foo;` declarations refer to, mapped to the files declaring them
+    pub module_files: HashMap<PathBuf, Vec<FileUrl>>,
+    /// Files being processed right now. Processing a file loads its parent, which can come back
+    /// around to the file through the parent's `mod` declarations.
+    pub updating: HashSet<FileUrl>,
 }
 
 impl Database {
@@ -120,6 +150,32 @@ impl Database {
             .expect("failed to send log");
     }
 
+    /// The edition of the package the file belongs to. Files outside of any package get the latest
+    /// one.
+    pub fn edition(&self, file: &FileUrl) -> Edition {
+        package_of(self, file.path()).map_or(Edition::CURRENT, |package| package.edition)
+    }
+
+    /// Whether the client can create files through workspace edits
+    pub fn supports_create_file(&self) -> bool {
+        self.initialize_params
+            .capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.workspace_edit.as_ref())
+            .and_then(|workspace_edit| workspace_edit.resource_operations.as_ref())
+            .is_some_and(|operations| operations.contains(&ResourceOperationKind::Create))
+    }
+
+    /// Whether the client requests diagnostics itself instead of waiting for them to be published
+    pub fn pulls_diagnostics(&self) -> bool {
+        self.initialize_params
+            .capabilities
+            .text_document
+            .as_ref()
+            .is_some_and(|text_document| text_document.diagnostic.is_some())
+    }
+
     /// Attempt to check a file from the database, and if it's missing, load it from the filesystem
     pub fn get_file(&mut self, file: &FileUrl) -> Option<&FileData> {
         if !self.files.contains_key(file) {
@@ -133,12 +189,12 @@ impl Database {
     pub fn load_file(&mut self, file: &FileUrl) -> std::io::Result<()> {
         // Don't update unless the file is missing; otherwise this will cause a lot of unnecessary
         // recomputes
-        if self.files.get(file).is_none() {
+        if !self.files.contains_key(file) {
             let file_src = std::fs::read_to_string(file.path())?;
             let data = FileData::default();
             let version = data.version;
             self.files.insert(file.clone(), data);
-            self.update_file(&file, version, &file_src);
+            self.update_file(file, version, &file_src);
         }
 
         Ok(())
@@ -146,57 +202,288 @@ impl Database {
 
     /// Assume the FileData exists and reprocess it
     pub fn update_file(&mut self, file: &FileUrl, version: i32, src: &str) {
-        let line_index = LineIndex::new(src);
+        let line_index = PositionIndex::new(src, self.position_encoding);
+        let edition = self.edition(file);
+        let ast = rust_analyzer_syntax::SourceFile::parse(src, edition);
+        self.files.get_mut(file).expect("file data missing").edition = edition;
+        self.update_file_parsed(file, version, src.to_string(), line_index, ast, None);
+    }
 
-        let ast = rust_analyzer_syntax::SourceFile::parse(src, Edition::Edition2024);
+    /// Assume the FileData exists, apply the client's changes to its stored text in order, and
+    /// reprocess it. Ranged changes are reparsed incrementally where possible, so only the edited
+    /// part of the syntax tree is rebuilt, and only the top level items they touch are rescanned.
+    /// Changes with ranges outside the file, inside a character or reversed are rejected without
+    /// applying any of them.
+    pub fn edit_file(
+        &mut self,
+        file: &FileUrl,
+        version: i32,
+        changes: Vec<TextDocumentContentChangeEvent>,
+    ) -> Result<(), Whatever> {
+        let file_data = self
+            .files
+            .get(file)
+            .with_whatever_context(|| format!("file {} was edited but not loaded", file.url()))?;
+        let mut text = file_data.text.clone();
+        let mut line_index = file_data.index.clone();
+        let mut ast = file_data.ast.clone();
+        let edition = file_data.edition;
+
+        // Where the changes put new text, if any; `Some(None)` until the first ranged change, and
+        // `None` once a change replaces the whole file, which has to be rescanned then
+        let mut edited = Some(None);
+        for change in changes {
+            match change.range {
+                Some(range) => {
+                    let delete = line_index.text_range(range).with_whatever_context(|| {
+                        format!("edit range {range:?} is not a valid range of the file")
+                    })?;
+
+                    ast = ast.reparse(delete, &change.text, edition);
+                    text.replace_range(std::ops::Range::<usize>::from(delete), &change.text);
+                    edited = edited.map(|edited| Some(add_edit(edited, delete, &change.text)));
+                }
+                None => {
+                    ast = SourceFile::parse(&change.text, edition);
+                    text = change.text;
+                    edited = None;
+                }
+            }
+            line_index = PositionIndex::new(&text, self.position_encoding);
+        }
 
-        let diagnostics = get_file_diagnostics(&ast, &line_index);
-        post_diagnostics(&self.connection, &file, diagnostics, version);
+        self.update_file_parsed(file, version, text, line_index, ast, edited.flatten());
+        Ok(())
+    }
 
-        let file_data = self.files.get_mut(file).expect("file data missing");
+    /// Store the new contents of a file and rescan it; only the top level items touching the
+    /// `edited` range of the text if given, or all of it otherwise
+    fn update_file_parsed(
+        &mut self,
+        file: &FileUrl,
+        version: i32,
+        text: String,
+        line_index: PositionIndex,
+        ast: Parse<SourceFile>,
+        edited: Option<TextRange>,
+    ) {
+        self.updating.insert(file.clone());
+        let old_includes = included_files(&self.files[file]);
+        let rescanned =
+            edited.is_some_and(|edited| rescan_edited(self, file, &line_index, &ast, edited));
+        if !rescanned {
+            self.clear_file(file, &old_includes);
+            scan_file_modules(self, file);
+            scan_ast(self, file, &line_index, ast.syntax_node());
+        }
 
-        for type_def in &file_data.types {
-            self.type_defs.remove(type_def);
+        let file_data = self.files.get_mut(file).expect("file data missing");
+        file_data.version = version;
+        file_data.text = text;
+        file_data.index = line_index;
+        file_data.ast = ast;
+        self.updating.remove(file);
+        let new_includes = included_files(file_data);
+
+        // Clients that pull diagnostics would otherwise see every diagnostic twice. Files declaring
+        // this one might have been missing it until now.
+        if !self.pulls_diagnostics() {
+            let declaring = self
+                .module_files
+                .get(file.path())
+                .cloned()
+                .unwrap_or_default();
+            for file in std::iter::once(file).chain(&declaring) {
+                if let Some(data) = self.files.get(file) {
+                    let diagnostics = file_diagnostics(file, data);
+                    post_diagnostics(&self.connection, file, diagnostics, data.version);
+                }
+            }
         }
 
-        for function_def in &file_data.functions {
-            self.function_defs.remove(function_def);
+        // Files whose declarations were added, removed or moved belong somewhere else now. Those
+        // already being processed will find their parent once they get to it.
+        for (path, _) in old_includes.symmetric_difference(&new_includes) {
+            let Ok(included) = FileUrl::from_path(path) else {
+                continue;
+            };
+            if self.updating.contains(&included) {
+                continue;
+            }
+            if self.files.contains_key(&included) {
+                self.rescan_file(&included);
+            } else {
+                _ = self.load_file(&included);
+            }
         }
+    }
 
-        scan_file_modules(self, file);
-        scan_ast(self, file, &line_index, ast.syntax_node());
+    /// Remove everything recorded for a file from the database, before scanning all of it again
+    fn clear_file(&mut self, file: &FileUrl, includes: &HashSet<(PathBuf, ModulePath)>) {
+        for (path, _) in includes {
+            if let Some(declaring) = self.module_files.get_mut(path) {
+                declaring.retain(|declaring| declaring != file);
+                if declaring.is_empty() {
+                    self.module_files.remove(path);
+                }
+            }
+        }
 
         let file_data = self.files.get_mut(file).expect("file data missing");
-        file_data.version = version;
-        file_data.index = line_index;
-        file_data.ast = ast;
+        file_data.modules.clear();
+        file_data.imports.clear();
+        file_data.impls.clear();
+
+        for item in std::mem::take(&mut file_data.items) {
+            if let Some(defs) = self.items.get_mut(&item) {
+                defs.retain(|def| &def.file_path != file);
+                if defs.is_empty() {
+                    self.items.remove(&item);
+                }
+            }
+        }
+
+        for owner in std::mem::take(&mut file_data.associated_owners) {
+            if let Some(associated_items) = self.associated_items.get_mut(&owner) {
+                associated_items.retain(|item| &item.file_path != file);
+                if associated_items.is_empty() {
+                    self.associated_items.remove(&owner);
+                }
+            }
+        }
+
+        for name in std::mem::take(&mut file_data.name_refs) {
+            if let Some(name_refs) = self.name_refs.get_mut(&name) {
+                name_refs.retain(|name_ref| &name_ref.file_path != file);
+                if name_refs.is_empty() {
+                    self.name_refs.remove(&name);
+                }
+            }
+        }
+
+        for symbol in std::mem::take(&mut file_data.symbols) {
+            self.symbols.remove(symbol);
+        }
+
+        for module in std::mem::take(&mut file_data.module_defs) {
+            self.modules.remove(&module);
+        }
+    }
+
+    /// Reprocess a file whose contents haven't changed, but whose place in the module tree might
+    /// have. Its child files are reprocessed too if its module path changed.
+    fn rescan_file(&mut self, file: &FileUrl) {
+        let Some(file_data) = self.files.get(file) else {
+            return;
+        };
+        let old_module_path = file_data.module_path.clone();
+        let version = file_data.version;
+        let text = file_data.text.clone();
+        let line_index = file_data.index.clone();
+        let ast = file_data.ast.clone();
+        self.update_file_parsed(file, version, text, line_index, ast, None);
+
+        if self.files[file].module_path == old_module_path {
+            return;
+        }
+        let children = self
+            .files
+            .iter()
+            .filter(|(_, data)| data.parent.as_ref() == Some(file))
+            .map(|(child, _)| child.clone())
+            .collect::<Vec<_>>();
+        for child in children {
+            self.rescan_file(&child);
+        }
     }
 }
 
+/// The range of text changed by an edit made of earlier changes covering `edited`, followed by one
+/// replacing `delete` with `insert`
+fn add_edit(edited: Option<TextRange>, delete: TextRange, insert: &str) -> TextRange {
+    let inserted = TextRange::at(delete.start(), TextSize::of(insert));
+    let Some(edited) = edited else {
+        return inserted;
+    };
+
+    // Where an offset into the text before the change ends up after it
+    let moved = |offset: TextSize| {
+        if offset <= delete.start() {
+            offset
+        } else if offset >= delete.end() {
+            offset - delete.len() + inserted.len()
+        } else {
+            inserted.end()
+        }
+    };
+    TextRange::new(moved(edited.start()), moved(edited.end())).cover(inserted)
+}
+
+/// The files `file` declares as modules, with the modules they're declared as
+fn included_files(file: &FileData) -> HashSet<(PathBuf, ModulePath)> {
+    file.modules
+        .iter()
+        .flat_map(|include| {
+            include
+                .paths
+                .iter()
+                .map(|path| (path.clone(), include.module.clone()))
+        })
+        .collect()
+}
+
 pub struct FileData {
     pub version: i32,
-    pub index: LineIndex,
+    /// The file's current contents, which incremental changes are applied to
+    pub text: String,
+    pub index: PositionIndex,
     pub is_open: bool,
     pub modules: Vec<ModuleInclude>,
     pub parent: Option<FileUrl>,
+    /// The path of the module this file is the root of
+    pub module_path: ModulePath,
     pub ast: Parse<SourceFile>,
-    /// Used to clear old type defs when a file is changed/removed
-    pub types: Vec<ItemPath>,
-    /// Used to clear old function defs when a file is changed/removed
-    pub functions: Vec<ItemPath>,
+    /// The edition the file is parsed with, its package's
+    pub edition: Edition,
+    /// Used to clear old module data (this file's own module and its inline modules) when a file
+    /// is changed/removed
+    pub module_defs: Vec<ModulePath>,
+    /// Every `use` in the file, including those in inline modules
+    pub imports: Vec<ImportData>,
+    /// Every `impl` block in the file, plus the impls implied by `#[derive]`s
+    pub impls: Vec<ImplData>,
+    /// Used to clear old item defs when a file is changed/removed
+    pub items: Vec<ItemPath>,
+    /// Used to clear old associated items when a file is changed/removed
+    pub associated_owners: Vec<String>,
+    /// Used to clear old name refs when a file is changed/removed
+    pub name_refs: Vec<String>,
+    /// Used to clear old workspace symbols when a file is changed/removed
+    pub symbols: Vec<SymbolId>,
+    /// The last full set of semantic tokens sent for the file, which deltas are computed against
+    pub semantic_tokens: Option<SemanticTokens>,
 }
 
 impl Default for FileData {
     fn default() -> Self {
         Self {
             version: 0,
-            index: LineIndex::new(""),
+            text: String::new(),
+            index: PositionIndex::new("", PositionEncoding::Wide(WideEncoding::Utf16)),
             is_open: false,
             modules: vec![],
             parent: None,
+            module_path: ModulePath::crate_root(),
             ast: SourceFile::parse("", Edition::Edition2015),
-            types: vec![],
-            functions: vec![],
+            edition: Edition::CURRENT,
+            module_defs: vec![],
+            imports: vec![],
+            impls: vec![],
+            items: vec![],
+            associated_owners: vec![],
+            name_refs: vec![],
+            symbols: vec![],
+            semantic_tokens: None,
         }
     }
 }
@@ -204,7 +491,18 @@ impl Default for FileData {
 #[derive(Debug)]
 pub struct ModuleInclude {
     pub name: String,
+    /// The path of the declared module, which includes any inline modules it's nested in
+    pub module: ModulePath,
+    /// The files a `mod foo;` declaration can refer to: `foo.rs` and `foo/mod.rs`, or those named by
+    /// its `#[path]` attributes (several when they're chosen by `#[cfg_attr]`). Empty for inline
+    /// modules.
+    pub paths: Vec<PathBuf>,
+    /// Whether `paths` come from `#[path]` attributes
+    pub path_attribute: bool,
+    pub visibility: Visibility,
     pub range: Range,
+    /// The module's name in the declaration
+    pub name_range: Range,
 }
 
 #[derive(Clone, Debug, Hash, PartialEq, Eq)]
@@ -213,9 +511,87 @@ pub struct ModulePath {
     pub segments: Vec<String>,
 }
 
+impl ModulePath {
+    pub fn crate_root() -> ModulePath {
+        ModulePath {
+            crate_: "crate".to_string(),
+            segments: vec![],
+        }
+    }
+
+    pub fn child(&self, name: &str) -> ModulePath {
+        let mut segments = self.segments.clone();
+        segments.push(name.to_string());
+        ModulePath {
+            crate_: self.crate_.clone(),
+            segments,
+        }
+    }
+
+    /// Returns `None` for a crate root
+    pub fn parent(&self) -> Option<ModulePath> {
+        let (_, segments) = self.segments.split_last()?;
+        Some(ModulePath {
+            crate_: self.crate_.clone(),
+            segments: segments.to_vec(),
+        })
+    }
+
+    /// The crate name followed by the module segments, e.g. `["crate", "net"]`
+    pub fn full_segments(&self) -> Vec<String> {
+        std::iter::once(self.crate_.clone())
+            .chain(self.segments.iter().cloned())
+            .collect()
+    }
+}
+
+#[derive(Debug)]
 pub struct ModuleData {
     pub name: String,
+    /// Names of the modules declared inside this one, either as `mod foo;` or `mod foo { }`
     pub children: Vec<String>,
+    /// Names of the items declared directly in this module, keys into [`Database::items`] along
+    /// with the module's path
+    pub items: Vec<String>,
+    /// The file the module's contents live in
+    pub file_path: FileUrl,
+    /// The whole file for file modules, or the `mod foo { }` block for inline modules
+    pub range: Range,
+}
+
+/// A single name brought into scope by a `use` declaration. Use trees are flattened, so
+/// `use foo::{Bar, baz::*};` produces two imports.
+#[derive(Debug)]
+pub struct ImportData {
+    /// The module the `use` appears in
+    pub module: ModulePath,
+    /// The name the import is visible as; `None` for glob imports and `as _` imports
+    pub name: Option<String>,
+    /// The imported path, exactly as written; may begin with `crate`, `self` or `super`
+    pub path: Vec<String>,
+    /// `use foo::*;`, in which case `path` is the module being globbed
+    pub glob: bool,
+    /// The visibility of the `use`, which makes `pub use` a re-export
+    pub visibility: Visibility,
+    pub range: Range,
+}
+
+#[derive(Debug)]
+pub struct NameRefData {
+    pub file_path: FileUrl,
+    pub range: Range,
+}
+
+#[derive(Debug)]
+pub struct ImplData {
+    /// The implemented trait as written, for trait impls
+    pub trait_: Option<Vec<String>>,
+    /// The self type as written; `None` if it isn't a plain path (like `&Foo`)
+    pub self_ty: Option<Vec<String>>,
+    /// The module the `impl` block is in, which the paths are resolved from
+    pub module: ModulePath,
+    /// The `impl` block, or the `#[derive]` attribute for derived impls
+    pub range: Range,
 }
 
 #[derive(Clone, Debug, Hash, PartialEq, Eq)]
@@ -224,16 +600,117 @@ pub struct ItemPath {
     pub name: String,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ItemKind {
+    Struct,
+    Enum,
+    Union,
+    Trait,
+    TypeAlias,
+    Fn,
+    Const,
+    Static,
+    Macro,
+    ExternCrate,
+}
+
+impl ItemKind {
+    /// `extern crate` isn't included, since it has to be named differently from other items
+    pub fn from_syntax_kind(kind: SyntaxKind) -> Option<ItemKind> {
+        Some(match kind {
+            SyntaxKind::STRUCT => ItemKind::Struct,
+            SyntaxKind::ENUM => ItemKind::Enum,
+            SyntaxKind::UNION => ItemKind::Union,
+            SyntaxKind::TRAIT => ItemKind::Trait,
+            SyntaxKind::TYPE_ALIAS => ItemKind::TypeAlias,
+            SyntaxKind::FN => ItemKind::Fn,
+            SyntaxKind::CONST => ItemKind::Const,
+            SyntaxKind::STATIC => ItemKind::Static,
+            SyntaxKind::MACRO_RULES | SyntaxKind::MACRO_DEF => ItemKind::Macro,
+            _ => return None,
+        })
+    }
+
+    pub fn symbol_kind(&self) -> SymbolKind {
+        match self {
+            ItemKind::Struct | ItemKind::Union => SymbolKind::STRUCT,
+            ItemKind::Enum => SymbolKind::ENUM,
+            ItemKind::Trait => SymbolKind::INTERFACE,
+            ItemKind::TypeAlias => SymbolKind::TYPE_PARAMETER,
+            ItemKind::Fn | ItemKind::Macro => SymbolKind::FUNCTION,
+            ItemKind::Const => SymbolKind::CONSTANT,
+            ItemKind::Static => SymbolKind::VARIABLE,
+            ItemKind::ExternCrate => SymbolKind::MODULE,
+        }
+    }
+
+    pub fn completion_kind(&self) -> CompletionItemKind {
+        match self {
+            ItemKind::Struct | ItemKind::Union => CompletionItemKind::STRUCT,
+            ItemKind::Enum => CompletionItemKind::ENUM,
+            ItemKind::Trait => CompletionItemKind::INTERFACE,
+            ItemKind::TypeAlias => CompletionItemKind::TYPE_PARAMETER,
+            ItemKind::Fn | ItemKind::Macro => CompletionItemKind::FUNCTION,
+            ItemKind::Const => CompletionItemKind::CONSTANT,
+            ItemKind::Static => CompletionItemKind::VARIABLE,
+            ItemKind::ExternCrate => CompletionItemKind::MODULE,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Visibility {
+    Private,
+    Public,
+    /// `pub(crate)`
+    Crate,
+    /// `pub(super)`
+    Super,
+    /// `pub(in path)`, storing the path as written
+    In(String),
+}
+
 #[derive(Debug)]
-pub struct TypeDefData {
+pub struct ItemData {
+    pub kind: ItemKind,
+    pub name: String,
+    pub visibility: Visibility,
     pub file_path: FileUrl,
     pub range: Range,
+    /// The item's name in its definition
+    pub name_range: Range,
+    /// Fields of structs and unions, or variants of enums
+    pub children: Vec<ChildData>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChildKind {
+    Field,
+    Variant,
+}
+
+#[derive(Debug)]
+pub struct ChildData {
+    pub kind: ChildKind,
+    /// Tuple fields are named by their index
     pub name: String,
+    pub range: Range,
+    /// The name in the definition, or the whole field for tuple fields
+    pub name_range: Range,
 }
 
 #[derive(Debug)]
-pub struct FunctionDefData {
+pub struct AssociatedItemData {
+    /// One of `Fn`, `Const` or `TypeAlias`
+    pub kind: ItemKind,
+    pub name: String,
+    /// The impl's self type or the trait's name, as written
+    pub owner: Vec<String>,
+    /// The module the `impl` or `trait` block is in, which `owner` is resolved from
+    pub module: ModulePath,
+    pub visibility: Visibility,
     pub file_path: FileUrl,
     pub range: Range,
-    pub name: String,
+    /// The item's name in its definition
+    pub name_range: Range,
 }
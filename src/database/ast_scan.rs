@@ -1,187 +1,674 @@
-use line_index::LineIndex;
-use rust_analyzer_syntax::ast::{Enum, Fn, HasName, Module, Struct};
+use std::collections::HashSet;
+
+use lsp_types::{Location, SymbolKind};
+use rust_analyzer_syntax::ast::{
+    self, AnyHasName, AnyHasVisibility, ExternCrate, HasAttrs, HasName, HasVisibility, Module, Use,
+    UseTree, VisibilityKind,
+};
 use rust_analyzer_syntax::{AstNode, SyntaxKind, SyntaxNode};
 use snafu::{OptionExt, Whatever};
 
+use crate::database::module::{declared_files, is_path_included, path_attributes};
+use crate::database::resolve::{associated_owner, path_segments};
+use crate::database::symbol_index::SymbolData;
 use crate::database::{
-    Database, FileUrl, FunctionDefData, ItemPath, ModuleInclude, ModulePath, TypeDefData,
+    AssociatedItemData, ChildData, ChildKind, Database, FileUrl, ImplData, ImportData, ItemData,
+    ItemKind, ItemPath, ModuleData, ModuleInclude, ModulePath, NameRefData, Visibility,
 };
+use crate::utils::PositionIndex;
+
+/// Scan a file's entire AST, registering the file's module and everything declared inside it
+pub fn scan_ast(db: &mut Database, file: &FileUrl, index: &PositionIndex, ast: SyntaxNode) {
+    let module = db
+        .files
+        .get(file)
+        .expect("failed to access file in AST scan")
+        .module_path
+        .clone();
+
+    let name = module.segments.last().unwrap_or(&module.crate_).clone();
+    let range = crate::utils::range(ast.text_range(), index);
+    insert_module(db, file, module.clone(), name, range);
+
+    collect_name_refs(db, file, index, &ast);
+    scan_node(db, file, index, ast, &module);
+}
+
+/// Scan some of a file's top level items, like [`scan_ast`] but without registering the file's
+/// module again, for rescanning the items an edit changed
+pub fn scan_items(db: &mut Database, file: &FileUrl, index: &PositionIndex, items: &[SyntaxNode]) {
+    let module = db
+        .files
+        .get(file)
+        .expect("failed to access file in AST scan")
+        .module_path
+        .clone();
+
+    for item in items {
+        collect_name_refs(db, file, index, item);
+        scan_node(db, file, index, item.clone(), &module);
+    }
+}
+
+/// Records every identifier that refers to something, for finding references
+fn collect_name_refs(db: &mut Database, file: &FileUrl, index: &PositionIndex, ast: &SyntaxNode) {
+    let mut names = HashSet::new();
+    for name_ref in ast.descendants().filter_map(ast::NameRef::cast) {
+        if name_ref.token_kind() != SyntaxKind::IDENT {
+            continue;
+        }
 
-/// Recursively calls itself to scan the entire AST node by node and extract information
-pub fn scan_ast(db: &mut Database, file: &FileUrl, index: &LineIndex, ast: SyntaxNode) {
-    let file_def = db
+        let name = name_ref.text_non_mutable().to_string();
+        let range = crate::utils::range(name_ref.syntax().text_range(), index);
+        db.name_refs
+            .entry(name.clone())
+            .or_default()
+            .push(NameRefData {
+                file_path: file.clone(),
+                range,
+            });
+        names.insert(name);
+    }
+
+    let file = db
         .files
         .get_mut(file)
         .expect("failed to access file in AST scan");
-    file_def.modules.clear();
+    file.name_refs.extend(names);
+}
 
+/// Recursively calls itself to scan the AST node by node and extract information. `module` is
+/// the path of the innermost module containing `ast`.
+fn scan_node(
+    db: &mut Database,
+    file: &FileUrl,
+    index: &PositionIndex,
+    ast: SyntaxNode,
+    module: &ModulePath,
+) {
     match ast.kind() {
         SyntaxKind::MODULE => {
-            let module = Module::cast(ast.clone()).expect("failed to cast module");
-            _ = collect_module(db, file, index, module);
+            let module_node = Module::cast(ast.clone()).expect("failed to cast module");
+            if let Ok(Some(inline_module)) = collect_module(db, file, index, module_node, module) {
+                for child in ast.children() {
+                    scan_node(db, file, index, child, &inline_module);
+                }
+                return;
+            }
         }
-        SyntaxKind::STRUCT => {
-            let struc = Struct::cast(ast.clone()).expect("failed to cast struct");
-            _ = collect_struct_def(db, file, index, struc);
+        SyntaxKind::USE => {
+            let use_ = Use::cast(ast.clone()).expect("failed to cast use");
+            collect_use(db, file, index, use_, module);
+            return;
         }
-        SyntaxKind::ENUM => {
-            let enu = Enum::cast(ast.clone()).expect("failed to cast enum");
-            _ = collect_enum_def(db, file, index, enu);
+        SyntaxKind::EXTERN_CRATE => {
+            let extern_crate = ExternCrate::cast(ast.clone()).expect("failed to cast extern crate");
+            _ = collect_extern_crate(db, file, index, extern_crate, module);
         }
-        SyntaxKind::FN => {
-            let func = Fn::cast(ast.clone()).expect("failed to cast fn");
-            _ = collect_fn_def(db, file, index, func);
+        SyntaxKind::IMPL => {
+            let impl_ = ast::Impl::cast(ast.clone()).expect("failed to cast impl");
+            collect_impl(db, file, index, impl_, module);
+        }
+        // Items in a block can only be named inside it (see `find_block_item`), but impls apply
+        // everywhere
+        SyntaxKind::BLOCK_EXPR => {
+            scan_block_impls(db, file, index, &ast, module);
+            return;
+        }
+        kind => {
+            let is_associated =
+                ast.parent().map(|parent| parent.kind()) == Some(SyntaxKind::ASSOC_ITEM_LIST);
+            if let Some(item_kind) = ItemKind::from_syntax_kind(kind) {
+                if is_associated {
+                    _ = collect_associated_item(db, file, index, ast.clone(), item_kind, module);
+                } else {
+                    _ = collect_item_def(db, file, index, ast.clone(), item_kind, module);
+                }
+
+                if let Some(adt) = ast::Adt::cast(ast.clone()) {
+                    collect_derives(db, file, index, adt, module);
+                }
+            }
         }
-        _ => (),
     }
 
     for child in ast.children() {
-        scan_ast(db, file, index, child);
+        scan_node(db, file, index, child, module);
     }
 }
 
+/// Scan the impls inside `node`, including those in nested blocks, in a single walk that skips
+/// everything else
+fn scan_block_impls(
+    db: &mut Database,
+    file: &FileUrl,
+    index: &PositionIndex,
+    node: &SyntaxNode,
+    module: &ModulePath,
+) {
+    for child in node.children() {
+        match child.kind() {
+            SyntaxKind::IMPL => scan_node(db, file, index, child, module),
+            _ => scan_block_impls(db, file, index, &child, module),
+        }
+    }
+}
+
+fn insert_module(
+    db: &mut Database,
+    file: &FileUrl,
+    module: ModulePath,
+    name: String,
+    range: lsp_types::Range,
+) {
+    // The crate root is named after the file, which isn't something anyone would search for
+    if let Some(parent) = module.parent() {
+        insert_symbol(
+            db,
+            file,
+            SymbolData {
+                name: name.clone(),
+                kind: SymbolKind::MODULE,
+                container: parent.full_segments(),
+                location: Location::new(file.url().clone(), range),
+            },
+        );
+    }
+
+    let module_data = ModuleData {
+        name,
+        children: vec![],
+        items: vec![],
+        file_path: file.clone(),
+        range,
+    };
+
+    // Files picked between by `#[cfg_attr(.., path = "...")]` are expected to share a module
+    if let Some(old) = db.modules.insert(module.clone(), module_data)
+        && &old.file_path != file
+        && !is_path_included(db, file)
+    {
+        db.log_warning(&format!(
+            "discarding module `{}` from {}; conflicting module path encountered",
+            old.name,
+            old.file_path.url()
+        ));
+    }
+
+    let file = db
+        .files
+        .get_mut(file)
+        .expect("failed to access file in AST scan");
+    file.module_defs.push(module);
+}
+
+/// Records the `mod` declaration in its file and parent module. Returns the path of the new
+/// module if it is an inline `mod foo { }` block, so that its contents can be scanned with it.
 fn collect_module(
     db: &mut Database,
     file: &FileUrl,
-    index: &LineIndex,
+    index: &PositionIndex,
     module: Module,
-) -> Result<(), Whatever> {
-    let name = module
-        .name()
-        .whatever_context("module had no name")?
-        .text_non_mutable()
-        .to_string();
+    parent: &ModulePath,
+) -> Result<Option<ModulePath>, Whatever> {
+    let name_node = module.name().whatever_context("module had no name")?;
+    let name = name_node.text_non_mutable().to_string();
     let range = crate::utils::range(module.syntax().text_range(), index);
+    let name_range = crate::utils::range(name_node.syntax().text_range(), index);
+    let path = parent.child(&name);
+
+    let paths = match module.item_list() {
+        Some(_) => vec![],
+        None => declared_files(db, file, &module),
+    };
+    for included in &paths {
+        let declaring = db.module_files.entry(included.clone()).or_default();
+        if !declaring.contains(file) {
+            declaring.push(file.clone());
+        }
+    }
+
+    if let Some(parent_data) = db.modules.get_mut(parent)
+        && !parent_data.children.contains(&name)
+    {
+        parent_data.children.push(name.clone());
+    }
+
+    let file_data = db
+        .files
+        .get_mut(file)
+        .expect("failed to access file in AST scan");
+    file_data.modules.push(ModuleInclude {
+        name: name.clone(),
+        module: path.clone(),
+        paths,
+        path_attribute: !path_attributes(&module).is_empty(),
+        visibility: get_visibility(module.visibility()),
+        range,
+        name_range,
+    });
+
+    if module.item_list().is_none() {
+        return Ok(None);
+    }
+
+    insert_module(db, file, path.clone(), name, range);
+    Ok(Some(path))
+}
+
+fn collect_use(
+    db: &mut Database,
+    file: &FileUrl,
+    index: &PositionIndex,
+    use_: Use,
+    module: &ModulePath,
+) {
+    let Some(tree) = use_.use_tree() else {
+        return;
+    };
+
+    let visibility = get_visibility(use_.visibility());
+    let mut imports = vec![];
+    collect_use_tree(index, tree, vec![], module, &visibility, &mut imports);
+
     let file = db
         .files
         .get_mut(file)
         .expect("failed to access file in AST scan");
-    file.modules.push(ModuleInclude { name, range });
-    Ok(())
+    file.imports.extend(imports);
+}
+
+/// Flatten a use tree into one import per name it brings into scope
+fn collect_use_tree(
+    index: &PositionIndex,
+    tree: UseTree,
+    mut path: Vec<String>,
+    module: &ModulePath,
+    visibility: &Visibility,
+    imports: &mut Vec<ImportData>,
+) {
+    if let Some(tree_path) = tree.path() {
+        let Some(segments) = path_segments(&tree_path) else {
+            return;
+        };
+        path.extend(segments);
+    }
+
+    if let Some(list) = tree.use_tree_list() {
+        for child in list.use_trees() {
+            collect_use_tree(index, child, path.clone(), module, visibility, imports);
+        }
+        return;
+    }
+
+    let range = crate::utils::range(tree.syntax().text_range(), index);
+
+    if tree.star_token().is_some() {
+        imports.push(ImportData {
+            module: module.clone(),
+            name: None,
+            path,
+            glob: true,
+            visibility: visibility.clone(),
+            range,
+        });
+        return;
+    }
+
+    // `use foo::{self};` imports `foo` itself
+    if path.last().is_some_and(|segment| segment == "self") && path.len() > 1 {
+        path.pop();
+    }
+
+    let name = match tree.rename() {
+        Some(rename) => rename
+            .name()
+            .map(|name| name.text_non_mutable().to_string()),
+        None => path.last().cloned(),
+    };
+
+    imports.push(ImportData {
+        module: module.clone(),
+        name,
+        path,
+        glob: false,
+        visibility: visibility.clone(),
+        range,
+    });
 }
 
-fn collect_struct_def(
+/// Records any named item that lives in a module's namespace
+fn collect_item_def(
     db: &mut Database,
     file: &FileUrl,
-    index: &LineIndex,
-    typedef: Struct,
+    index: &PositionIndex,
+    item: SyntaxNode,
+    kind: ItemKind,
+    module: &ModulePath,
 ) -> Result<(), Whatever> {
-    let name = typedef
-        .name()
-        .whatever_context("struct definition had no name")?
-        .text_non_mutable()
-        .to_string();
-    let range = crate::utils::range(typedef.syntax().text_range(), index);
+    let name_node = AnyHasName::cast(item.clone())
+        .and_then(|item| item.name())
+        .with_whatever_context(|| format!("{kind:?} definition had no name"))?;
+    let name = name_node.text_non_mutable().to_string();
+    let name_range = crate::utils::range(name_node.syntax().text_range(), index);
+    let visibility =
+        get_visibility(AnyHasVisibility::cast(item.clone()).and_then(|item| item.visibility()));
+    let range = crate::utils::range(item.text_range(), index);
+    let children = get_children(&item, index);
 
-    let item_path = ItemPath {
-        module: ModulePath {
-            crate_: "crate".to_string(),
-            segments: vec![],
-        },
-        name: name.clone(),
-    };
-    let item_data = TypeDefData {
+    let item_data = ItemData {
+        kind,
+        name,
+        visibility,
         file_path: file.clone(),
         range,
-        name,
+        name_range,
+        children,
     };
 
-    if let Some(old) = db.type_defs.insert(item_path.clone(), item_data) {
-        db.log_warning(&format!(
-            "discarding type def `{}`; conflicting type name encountered",
-            old.name
-        ));
+    insert_item_def(db, file, module, item_data);
+    Ok(())
+}
+
+/// Fields of structs and unions, or variants of enums
+fn get_children(item: &SyntaxNode, index: &PositionIndex) -> Vec<ChildData> {
+    let child = |kind, name: &SyntaxNode, node: &SyntaxNode| ChildData {
+        kind,
+        name: name.text().to_string(),
+        range: crate::utils::range(node.text_range(), index),
+        name_range: crate::utils::range(name.text_range(), index),
+    };
+    let record_fields = |list: ast::RecordFieldList| {
+        list.fields()
+            .filter_map(|field| {
+                Some(child(
+                    ChildKind::Field,
+                    field.name()?.syntax(),
+                    field.syntax(),
+                ))
+            })
+            .collect()
+    };
+
+    if let Some(enum_) = ast::Enum::cast(item.clone()) {
+        return enum_
+            .variant_list()
+            .into_iter()
+            .flat_map(|list| list.variants())
+            .filter_map(|variant| {
+                Some(child(
+                    ChildKind::Variant,
+                    variant.name()?.syntax(),
+                    variant.syntax(),
+                ))
+            })
+            .collect();
     }
 
+    if let Some(union_) = ast::Union::cast(item.clone()) {
+        return union_
+            .record_field_list()
+            .map(record_fields)
+            .unwrap_or_default();
+    }
+
+    match ast::Struct::cast(item.clone()).and_then(|struct_| struct_.field_list()) {
+        Some(ast::FieldList::RecordFieldList(list)) => record_fields(list),
+        Some(ast::FieldList::TupleFieldList(list)) => list
+            .fields()
+            .enumerate()
+            .map(|(i, field)| ChildData {
+                kind: ChildKind::Field,
+                name: i.to_string(),
+                range: crate::utils::range(field.syntax().text_range(), index),
+                name_range: crate::utils::range(field.syntax().text_range(), index),
+            })
+            .collect(),
+        None => vec![],
+    }
+}
+
+/// Records a `fn`, `const` or `type` declared in an `impl` or `trait` block under its owner
+fn collect_associated_item(
+    db: &mut Database,
+    file: &FileUrl,
+    index: &PositionIndex,
+    item: SyntaxNode,
+    kind: ItemKind,
+    module: &ModulePath,
+) -> Result<(), Whatever> {
+    let name_node = AnyHasName::cast(item.clone())
+        .and_then(|item| item.name())
+        .with_whatever_context(|| format!("associated {kind:?} had no name"))?;
+    let name = name_node.text_non_mutable().to_string();
+    let name_range = crate::utils::range(name_node.syntax().text_range(), index);
+    let owner = item
+        .ancestors()
+        .find_map(associated_owner)
+        .whatever_context("associated item had no nameable owner")?;
+    let owner_name = owner.last().expect("owner path was empty").clone();
+    let visibility =
+        get_visibility(AnyHasVisibility::cast(item.clone()).and_then(|item| item.visibility()));
+    let range = crate::utils::range(item.text_range(), index);
+
+    let symbol_kind = match kind {
+        ItemKind::Fn => SymbolKind::METHOD,
+        kind => kind.symbol_kind(),
+    };
+    let mut container = module.full_segments();
+    container.extend(owner.iter().cloned());
+    insert_symbol(
+        db,
+        file,
+        SymbolData {
+            name: name.clone(),
+            kind: symbol_kind,
+            container,
+            location: Location::new(file.url().clone(), range),
+        },
+    );
+
+    db.associated_items
+        .entry(owner_name.clone())
+        .or_default()
+        .push(AssociatedItemData {
+            kind,
+            name,
+            owner,
+            module: module.clone(),
+            visibility,
+            file_path: file.clone(),
+            range,
+            name_range,
+        });
+
     let file = db
         .files
         .get_mut(file)
         .expect("failed to access file in AST scan");
-    file.types.push(item_path);
+    if !file.associated_owners.contains(&owner_name) {
+        file.associated_owners.push(owner_name);
+    }
 
     Ok(())
 }
 
-fn collect_enum_def(
+fn collect_extern_crate(
     db: &mut Database,
     file: &FileUrl,
-    index: &LineIndex,
-    typedef: Enum,
+    index: &PositionIndex,
+    extern_crate: ExternCrate,
+    module: &ModulePath,
 ) -> Result<(), Whatever> {
-    let name = typedef
-        .name()
-        .whatever_context("enum definition had no name")?
-        .text_non_mutable()
-        .to_string();
-    let range = crate::utils::range(typedef.syntax().text_range(), index);
-
-    let item_path = ItemPath {
-        module: ModulePath {
-            crate_: "crate".to_string(),
-            segments: vec![],
-        },
-        name: name.clone(),
+    let name_node = match extern_crate.rename() {
+        Some(rename) => rename
+            .name()
+            .whatever_context("`extern crate` was renamed to `_`")?
+            .syntax()
+            .clone(),
+        None => extern_crate
+            .name_ref()
+            .whatever_context("`extern crate` had no name")?
+            .syntax()
+            .clone(),
     };
-    let item_data = TypeDefData {
+    let name = name_node.text().to_string();
+    let name_range = crate::utils::range(name_node.text_range(), index);
+    let visibility = get_visibility(extern_crate.visibility());
+    let range = crate::utils::range(extern_crate.syntax().text_range(), index);
+
+    let item_data = ItemData {
+        kind: ItemKind::ExternCrate,
+        name,
+        visibility,
         file_path: file.clone(),
         range,
-        name,
+        name_range,
+        children: vec![],
     };
 
-    if let Some(old) = db.type_defs.insert(item_path.clone(), item_data) {
-        db.log_warning(&format!(
-            "discarding type def `{}`; conflicting type name encountered",
-            old.name
-        ));
+    insert_item_def(db, file, module, item_data);
+    Ok(())
+}
+
+fn insert_item_def(db: &mut Database, file: &FileUrl, module: &ModulePath, item_data: ItemData) {
+    let item_path = ItemPath {
+        module: module.clone(),
+        name: item_data.name.clone(),
+    };
+    insert_symbol(
+        db,
+        file,
+        SymbolData {
+            name: item_data.name.clone(),
+            kind: item_data.kind.symbol_kind(),
+            container: module.full_segments(),
+            location: Location::new(file.url().clone(), item_data.range),
+        },
+    );
+
+    if let Some(module_data) = db.modules.get_mut(module)
+        && !module_data.items.contains(&item_path.name)
+    {
+        module_data.items.push(item_path.name.clone());
     }
+    db.items
+        .entry(item_path.clone())
+        .or_default()
+        .push(item_data);
 
     let file = db
         .files
         .get_mut(file)
         .expect("failed to access file in AST scan");
-    file.types.push(item_path);
+    file.items.push(item_path);
+}
 
-    Ok(())
+fn insert_symbol(db: &mut Database, file: &FileUrl, symbol: SymbolData) {
+    let id = db.symbols.insert(symbol);
+
+    let file = db
+        .files
+        .get_mut(file)
+        .expect("failed to access file in AST scan");
+    file.symbols.push(id);
 }
 
-fn collect_fn_def(
+fn collect_impl(
     db: &mut Database,
     file: &FileUrl,
-    index: &LineIndex,
-    function_def: Fn,
-) -> Result<(), Whatever> {
-    let name = function_def
-        .name()
-        .whatever_context("function definition had no name")?
-        .text_non_mutable()
-        .to_string();
-    let range = crate::utils::range(function_def.syntax().text_range(), index);
+    index: &PositionIndex,
+    impl_: ast::Impl,
+    module: &ModulePath,
+) {
+    let type_path = |ty: ast::Type| match ty {
+        ast::Type::PathType(path_type) => path_segments(&path_type.path()?),
+        _ => None,
+    };
 
-    let item_path = ItemPath {
-        module: ModulePath {
-            crate_: "crate".to_string(),
-            segments: vec![],
-        },
-        name: name.clone(),
+    let impl_data = ImplData {
+        trait_: impl_.trait_().and_then(type_path),
+        self_ty: impl_.self_ty().and_then(type_path),
+        module: module.clone(),
+        range: crate::utils::range(impl_.syntax().text_range(), index),
     };
-    let item_data = FunctionDefData {
-        file_path: file.clone(),
-        range,
-        name,
+
+    let file = db
+        .files
+        .get_mut(file)
+        .expect("failed to access file in AST scan");
+    file.impls.push(impl_data);
+}
+
+/// `#[derive(Foo)]` is assumed to implement a trait named `Foo`
+fn collect_derives(
+    db: &mut Database,
+    file: &FileUrl,
+    index: &PositionIndex,
+    adt: ast::Adt,
+    module: &ModulePath,
+) {
+    let Some(name) = adt.name() else {
+        return;
     };
+    let self_ty = vec![name.text_non_mutable().to_string()];
 
-    if let Some(old) = db.function_defs.insert(item_path.clone(), item_data) {
-        db.log_warning(&format!(
-            "discarding fn def `{}`; conflicting function name encountered",
-            old.name
-        ));
+    let mut impls = vec![];
+    for attr in adt.attrs() {
+        let Some((name, token_tree)) = attr.as_simple_call() else {
+            continue;
+        };
+        if name != "derive" {
+            continue;
+        }
+        let range = crate::utils::range(attr.syntax().text_range(), index);
+
+        for trait_ in derive_paths(&token_tree) {
+            impls.push(ImplData {
+                trait_: Some(trait_),
+                self_ty: Some(self_ty.clone()),
+                module: module.clone(),
+                range,
+            });
+        }
     }
 
     let file = db
         .files
         .get_mut(file)
         .expect("failed to access file in AST scan");
-    file.functions.push(item_path);
+    file.impls.extend(impls);
+}
 
-    Ok(())
+/// Split the `(Foo, bar::Baz)` of a `#[derive]` into paths
+fn derive_paths(token_tree: &ast::TokenTree) -> Vec<Vec<String>> {
+    let mut paths = vec![];
+    let mut current = vec![];
+    for token in token_tree
+        .syntax()
+        .descendants_with_tokens()
+        .filter_map(|element| element.into_token())
+    {
+        if token.kind() == SyntaxKind::COMMA {
+            paths.extend((!current.is_empty()).then(|| std::mem::take(&mut current)));
+        } else if token.kind().is_any_identifier() {
+            current.push(token.text().to_string());
+        }
+    }
+    paths.extend((!current.is_empty()).then_some(current));
+    paths
+}
+
+fn get_visibility(visibility: Option<ast::Visibility>) -> Visibility {
+    let Some(visibility) = visibility else {
+        return Visibility::Private;
+    };
+
+    match visibility.kind() {
+        VisibilityKind::Pub => Visibility::Public,
+        VisibilityKind::PubCrate => Visibility::Crate,
+        VisibilityKind::PubSuper => Visibility::Super,
+        VisibilityKind::PubSelf => Visibility::Private,
+        VisibilityKind::In(path) => Visibility::In(path.syntax().text().to_string()),
+    }
 }
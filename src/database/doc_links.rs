@@ -0,0 +1,321 @@
+use std::ops::Range;
+
+use lsp_types::{Location, Url};
+use rust_analyzer_syntax::ast::{self, AstToken, HasName};
+use rust_analyzer_syntax::{AstNode, SyntaxKind, SyntaxNode, SyntaxToken, TextRange, TextSize};
+
+use crate::database::resolve::{replace_self_type, resolve_path};
+use crate::database::{Database, ModulePath};
+
+/// An intra-doc link, like ``[`Foo`]``, `[text](crate::Foo)`, `[text][Foo]` or `[label]: Foo`
+#[derive(Clone, Debug)]
+pub struct DocLink {
+    /// The path the link points to, as written
+    pub path: Vec<String>,
+    /// Where the path is written in the doc text
+    pub range: Range<usize>,
+    /// The text to replace with a URL to make the link clickable in rendered markdown. Empty for
+    /// shortcut links, where the URL is inserted after the link text.
+    pub rewrite: Range<usize>,
+    /// Whether the URL has to be wrapped in parentheses, because it's replacing or adding an
+    /// inline destination rather than filling in a destination that's already there
+    pub wrap: bool,
+}
+
+/// Finds links line by line, remembering whether it's in a code block in between
+#[derive(Default)]
+pub struct DocLinkParser {
+    in_code_block: bool,
+}
+
+impl DocLinkParser {
+    /// Parse every line of `text`, which starts at `offset` in whatever the ranges are relative to
+    pub fn parse(&mut self, text: &str, offset: usize, links: &mut Vec<DocLink>) {
+        let mut line_start = offset;
+        for line in text.split_inclusive('\n') {
+            self.parse_line(line, line_start, links);
+            line_start += line.len();
+        }
+    }
+
+    fn parse_line(&mut self, line: &str, offset: usize, links: &mut Vec<DocLink>) {
+        if line.trim_start().starts_with("```") || line.trim_start().starts_with("~~~") {
+            self.in_code_block = !self.in_code_block;
+            return;
+        }
+        if self.in_code_block {
+            return;
+        }
+
+        let mut position = 0;
+        while let Some(open) = line[position..].find('[').map(|open| open + position) {
+            let Some(close) = line[open..].find(']').map(|close| close + open) else {
+                break;
+            };
+            let after = &line[close + 1..];
+            position = close + 1;
+
+            // `[text](destination)`
+            if after.starts_with('(')
+                && let Some(end) = after.find(')').map(|end| end + close + 1)
+            {
+                position = end + 1;
+                push_link(links, line, close + 2..end, offset, None);
+                continue;
+            }
+
+            // `[text][destination]`
+            if after.starts_with('[')
+                && let Some(end) = after.find(']').map(|end| end + close + 1)
+            {
+                position = end + 1;
+                push_link(
+                    links,
+                    line,
+                    close + 2..end,
+                    offset,
+                    Some(close + 1..end + 1),
+                );
+                continue;
+            }
+
+            // `[label]: destination`, which has to start the line
+            if after.starts_with(':') && line[..open].trim().is_empty() {
+                push_link(links, line, close + 2..line.len(), offset, None);
+                break;
+            }
+
+            // `[destination]`
+            push_link(
+                links,
+                line,
+                open + 1..close,
+                offset,
+                Some(close + 1..close + 1),
+            );
+        }
+    }
+}
+
+/// Add the link with its destination at `destination` in `line`, if it looks like a path. The
+/// URL replaces `wrapped` in parentheses if given, or the destination itself otherwise.
+fn push_link(
+    links: &mut Vec<DocLink>,
+    line: &str,
+    destination: Range<usize>,
+    offset: usize,
+    wrapped: Option<Range<usize>>,
+) {
+    let text = &line[destination.clone()];
+    let trimmed = text.trim().trim_matches('`');
+    let start = destination.start + text.find(trimmed).unwrap_or(0);
+    let range = offset + start..offset + start + trimmed.len();
+
+    let Some(path) = link_path(trimmed) else {
+        return;
+    };
+
+    let (rewrite, wrap) = match wrapped {
+        Some(wrapped) => (offset + wrapped.start..offset + wrapped.end, true),
+        None => (range.clone(), false),
+    };
+    links.push(DocLink {
+        path,
+        range,
+        rewrite,
+        wrap,
+    });
+}
+
+/// The path a link destination points to, ignoring rustdoc's disambiguators (`struct@Foo`,
+/// `foo()`, `foo!`) and generic arguments. Returns `None` for anything that isn't a path, like
+/// URLs and relative file links.
+fn link_path(destination: &str) -> Option<Vec<String>> {
+    let destination = destination
+        .split_once('@')
+        .map_or(destination, |(_, path)| path);
+    let destination = destination.trim_end_matches("()").trim_end_matches('!');
+
+    let mut depth = 0;
+    let destination = destination
+        .chars()
+        .filter(|c| {
+            match c {
+                '<' => depth += 1,
+                '>' => depth -= 1,
+                _ => return depth == 0,
+            }
+            false
+        })
+        .collect::<String>();
+
+    let segments = destination
+        .split("::")
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    let is_identifier = |segment: &String| {
+        segment
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_alphabetic() || c == '_')
+            && segment.chars().all(|c| c.is_alphanumeric() || c == '_')
+    };
+
+    segments.iter().all(is_identifier).then_some(segments)
+}
+
+/// Every intra-doc link in the doc comments under `root`, as the comment it's in and the range of
+/// its path in the file. Paths starting with `Self` are replaced with the enclosing impl's type.
+pub fn file_doc_links(root: &SyntaxNode) -> Vec<(SyntaxToken, TextRange, Vec<String>)> {
+    let mut links = vec![];
+    let mut parser = DocLinkParser::default();
+    let mut previous_parent = None;
+
+    for token in root
+        .descendants_with_tokens()
+        .filter_map(|element| element.into_token())
+        .filter(|token| token.kind() == SyntaxKind::COMMENT)
+    {
+        let Some(comment) = ast::Comment::cast(token.clone()) else {
+            continue;
+        };
+        let Some(text) = comment.doc_comment() else {
+            continue;
+        };
+
+        // Code blocks can't span the docs of different items
+        if previous_parent != token.parent() {
+            parser = DocLinkParser::default();
+            previous_parent = token.parent();
+        }
+
+        let offset = usize::from(token.text_range().start()) + comment.prefix().len();
+        let mut comment_links = vec![];
+        parser.parse(text, offset, &mut comment_links);
+
+        for link in comment_links {
+            let Some(parent) = token.parent() else {
+                continue;
+            };
+            let Some(path) = replace_doc_self_type(&parent, link.path) else {
+                continue;
+            };
+
+            let range = TextRange::new(
+                TextSize::from(link.range.start as u32),
+                TextSize::from(link.range.end as u32),
+            );
+            links.push((token.clone(), range, path));
+        }
+    }
+
+    links
+}
+
+/// Replace a leading `Self` in a link from the docs of `node`. On a type definition `Self` is the
+/// type itself; otherwise it's the enclosing impl's type.
+pub fn replace_doc_self_type(node: &SyntaxNode, mut path: Vec<String>) -> Option<Vec<String>> {
+    if let Some(adt) = ast::Adt::cast(node.clone())
+        && path.first().is_some_and(|segment| segment == "Self")
+    {
+        path[0] = adt.name()?.text_non_mutable().to_string();
+        return Some(path);
+    }
+
+    replace_self_type(node, path)
+}
+
+/// The path of the intra-doc link at `offset` in the doc comment `token`
+pub fn doc_link_at(token: &SyntaxToken, offset: TextSize) -> Option<Vec<String>> {
+    let root = token.parent_ancestors().last()?;
+    file_doc_links(&root)
+        .into_iter()
+        .find(|(link_token, range, _)| link_token == token && range.contains_inclusive(offset))
+        .map(|(_, _, path)| path)
+}
+
+/// Where the link to `path` from docs in `module` goes. Unlike other paths, links aren't guessed at
+/// by name when they can't be resolved, since rustdoc wouldn't either.
+pub fn resolve_doc_link(db: &Database, module: &ModulePath, path: &[String]) -> Option<Location> {
+    resolve_path(db, module, path)?
+        .locations(db)
+        .into_iter()
+        .next()
+}
+
+/// A `file://` URL pointing at the line of `location`, for editors to open
+pub fn location_url(location: &Location) -> Url {
+    let mut url = location.uri.clone();
+    url.set_fragment(Some(&format!("L{}", location.range.start.line + 1)));
+    url
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Each link's path, the text at its range, the text it rewrites and whether it's wrapped
+    fn parse(text: &str) -> Vec<(String, &str, &str, bool)> {
+        let mut links = vec![];
+        DocLinkParser::default().parse(text, 0, &mut links);
+        links
+            .into_iter()
+            .map(|link| {
+                (
+                    link.path.join("::"),
+                    &text[link.range],
+                    &text[link.rewrite],
+                    link.wrap,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parses_each_link_form() {
+        assert_eq!(parse("See [`Foo`]."), [("Foo".into(), "Foo", "", true)]);
+        assert_eq!(
+            parse("[text](crate::a::Foo)"),
+            [(
+                "crate::a::Foo".into(),
+                "crate::a::Foo",
+                "crate::a::Foo",
+                false
+            )]
+        );
+        assert_eq!(
+            parse("[text][Foo] after"),
+            [("Foo".into(), "Foo", "[Foo]", true)]
+        );
+        assert_eq!(
+            parse("[label]: super::Foo"),
+            [("super::Foo".into(), "super::Foo", "super::Foo", false)]
+        );
+    }
+
+    #[test]
+    fn finds_several_links_across_lines() {
+        let links = parse("[A] and [B](b::C)\n[`D`]");
+        let paths = links.iter().map(|link| link.1).collect::<Vec<_>>();
+        assert_eq!(paths, ["A", "b::C", "D"]);
+    }
+
+    #[test]
+    fn skips_code_blocks() {
+        let links = parse("```\n[A]\n```\n[B]\n~~~rust\n[C]\n~~~");
+        let paths = links.iter().map(|link| link.1).collect::<Vec<_>>();
+        assert_eq!(paths, ["B"]);
+    }
+
+    #[test]
+    fn link_paths_ignore_disambiguators_and_generics() {
+        let path = |destination| link_path(destination).map(|path| path.join("::"));
+        assert_eq!(path("struct@Foo"), Some("Foo".into()));
+        assert_eq!(path("foo()"), Some("foo".into()));
+        assert_eq!(path("vec!"), Some("vec".into()));
+        assert_eq!(path("Vec<Option<T>>::new"), Some("Vec::new".into()));
+        assert_eq!(path("https://example.com"), None);
+        assert_eq!(path("../foo.md"), None);
+        assert_eq!(path("1abc"), None);
+    }
+}
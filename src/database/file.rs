@@ -2,19 +2,17 @@ use std::ffi::OsStr;
 use std::fs::DirEntry;
 use std::path::Path;
 
-use line_index::LineIndex;
 use lsp_server::{Connection, Message, Notification};
 use lsp_types::notification::{Notification as _, PublishDiagnostics};
-use lsp_types::{Diagnostic, DiagnosticSeverity, PublishDiagnosticsParams};
+use lsp_types::{Diagnostic, DiagnosticSeverity, NumberOrString, PublishDiagnosticsParams};
 use rust_analyzer_syntax::{Parse, SourceFile};
 use snafu::{ResultExt, Whatever};
 
-use crate::database::{Database, FileUrl};
-use crate::utils::OrLog;
+use crate::database::{Database, FileData, FileUrl};
+use crate::utils::{OrLog, PositionIndex};
 
-pub fn get_file_diagnostics(ast: &Parse<SourceFile>, index: &LineIndex) -> Vec<Diagnostic> {
-    let errors = ast
-        .errors()
+pub fn get_file_diagnostics(ast: &Parse<SourceFile>, index: &PositionIndex) -> Vec<Diagnostic> {
+    ast.errors()
         .iter()
         .map(|error| {
             let range = ast
@@ -28,9 +26,89 @@ pub fn get_file_diagnostics(ast: &Parse<SourceFile>, index: &LineIndex) -> Vec<D
                 ..Default::default()
             }
         })
-        .collect();
+        .collect()
+}
+
+/// Every diagnostic for a file: its syntax errors, and its `mod` declarations whose files are
+/// missing or ambiguous
+pub fn file_diagnostics(file: &FileUrl, data: &FileData) -> Vec<Diagnostic> {
+    let mut diagnostics = get_file_diagnostics(&data.ast, &data.index);
+    diagnostics.extend(module_diagnostics(file, data));
+    diagnostics
+}
+
+/// Errors for `mod foo;` declarations without a file, and warnings for those with both `foo.rs`
+/// and `foo/mod.rs`. Every file named by `#[path]` attributes has to exist, since each is used
+/// for some target.
+pub fn module_diagnostics(file: &FileUrl, data: &FileData) -> Vec<Diagnostic> {
+    let dir = file.path().parent().unwrap_or(Path::new("/"));
+    let display = |path: &Path| {
+        let path = path.strip_prefix(dir).unwrap_or(path);
+        format!("`{}`", path.display())
+    };
+
+    let mut diagnostics = vec![];
+    for include in &data.modules {
+        let (existing, missing) = include
+            .paths
+            .iter()
+            .partition::<Vec<_>, _>(|path| path.is_file());
+        let name = &include.name;
+
+        if include.path_attribute {
+            diagnostics.extend(missing.into_iter().map(|path| Diagnostic {
+                severity: Some(DiagnosticSeverity::ERROR),
+                range: include.range,
+                code: Some(NumberOrString::String("E0583".to_string())),
+                message: format!("file not found for module `{name}`: {}", display(path)),
+                ..Default::default()
+            }));
+            continue;
+        }
+
+        match existing[..] {
+            [] if !missing.is_empty() => diagnostics.push(Diagnostic {
+                severity: Some(DiagnosticSeverity::ERROR),
+                range: include.range,
+                code: Some(NumberOrString::String("E0583".to_string())),
+                message: format!(
+                    "file not found for module `{name}`; create {}",
+                    missing
+                        .iter()
+                        .map(|path| display(path))
+                        .collect::<Vec<_>>()
+                        .join(" or ")
+                ),
+                ..Default::default()
+            }),
+            [first, second, ..] => diagnostics.push(Diagnostic {
+                severity: Some(DiagnosticSeverity::WARNING),
+                range: include.range,
+                code: Some(NumberOrString::String("E0761".to_string())),
+                message: format!(
+                    "file for module `{name}` found at both {} and {}",
+                    display(first),
+                    display(second)
+                ),
+                ..Default::default()
+            }),
+            _ => {}
+        }
+    }
 
-    errors
+    diagnostics
+}
+
+/// Diagnostics depend on a file's contents, so its version identifies them for pull requests,
+/// along with which of the files its `mod` declarations refer to exist
+pub fn diagnostic_result_id(file: &FileData) -> String {
+    let existing = file
+        .modules
+        .iter()
+        .flat_map(|include| &include.paths)
+        .map(|path| if path.is_file() { '1' } else { '0' })
+        .collect::<String>();
+    format!("{}-{existing}", file.version)
 }
 
 pub fn post_diagnostics(
@@ -108,11 +186,85 @@ pub fn find_rust_files(db: &mut Database, root: &Path) -> Result<(), Whatever> {
     Ok(())
 }
 
-fn should_skip_dir(entry: &DirEntry) -> bool {
-    if let Ok(ty) = entry.file_type() {
-        if ty.is_file() {
-            return entry.file_name() == OsStr::new("CACHEDIR.TAG");
+pub fn should_skip_dir(entry: &DirEntry) -> bool {
+    if let Ok(ty) = entry.file_type()
+        && ty.is_file()
+    {
+        return entry.file_name() == OsStr::new("CACHEDIR.TAG");
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{ModuleInclude, ModulePath, Visibility};
+    use crate::test_utils::TempDir;
+
+    fn include(dir: &TempDir, name: &str, paths: &[&str], path_attribute: bool) -> ModuleInclude {
+        ModuleInclude {
+            name: name.to_string(),
+            module: ModulePath::crate_root().child(name),
+            paths: paths.iter().map(|path| dir.0.join(path)).collect(),
+            path_attribute,
+            visibility: Visibility::Private,
+            range: Default::default(),
+            name_range: Default::default(),
         }
     }
-    return false;
+
+    fn diagnostics(dir: &TempDir, modules: Vec<ModuleInclude>) -> Vec<(String, String)> {
+        let file = FileUrl::from_path(&dir.0.join("lib.rs")).unwrap();
+        let data = FileData {
+            modules,
+            ..FileData::default()
+        };
+        module_diagnostics(&file, &data)
+            .into_iter()
+            .map(|diagnostic| match diagnostic.code {
+                Some(NumberOrString::String(code)) => (code, diagnostic.message),
+                _ => panic!("diagnostic without a code"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reports_missing_and_ambiguous_module_files() {
+        let dir = TempDir::new(
+            "module-diagnostics",
+            &[("lib.rs", ""), ("a.rs", ""), ("b.rs", ""), ("b/mod.rs", "")],
+        );
+        let modules = vec![
+            include(&dir, "a", &["a.rs", "a/mod.rs"], false),
+            include(&dir, "b", &["b.rs", "b/mod.rs"], false),
+            include(&dir, "c", &["c.rs", "c/mod.rs"], false),
+            include(&dir, "inline", &[], false),
+        ];
+        assert_eq!(
+            diagnostics(&dir, modules),
+            [
+                (
+                    "E0761".to_string(),
+                    "file for module `b` found at both `b.rs` and `b/mod.rs`".to_string()
+                ),
+                (
+                    "E0583".to_string(),
+                    "file not found for module `c`; create `c.rs` or `c/mod.rs`".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn every_path_attribute_file_has_to_exist() {
+        let dir = TempDir::new("path-diagnostics", &[("lib.rs", ""), ("unix.rs", "")]);
+        let modules = vec![include(&dir, "sys", &["unix.rs", "windows.rs"], true)];
+        assert_eq!(
+            diagnostics(&dir, modules),
+            [(
+                "E0583".to_string(),
+                "file not found for module `sys`: `windows.rs`".to_string()
+            )]
+        );
+    }
 }
@@ -0,0 +1,486 @@
+use std::collections::HashSet;
+
+use lsp_types::{Position, Range};
+use rust_analyzer_syntax::{Parse, SourceFile, SyntaxNode, TextRange, TextSize};
+
+use crate::database::ast_scan::scan_items;
+use crate::database::{Database, FileUrl, ItemData, ItemPath};
+use crate::utils::PositionIndex;
+
+/// The part of a file an edit replaced. Positions before `start` are the same before and after the
+/// edit, and the text at `old_end` and after it moved to `new_end`.
+struct Region {
+    start: Position,
+    old_end: Position,
+    new_end: Position,
+}
+
+impl Region {
+    /// Whether `range` was entirely inside the replaced part
+    fn contains(&self, range: &Range) -> bool {
+        self.start <= range.start && range.end <= self.old_end
+    }
+
+    fn shift(&self, position: Position) -> Position {
+        if position < self.old_end {
+            return position;
+        }
+
+        let character = match position.line == self.old_end.line {
+            true => position.character - self.old_end.character + self.new_end.character,
+            false => position.character,
+        };
+        Position {
+            line: position.line - self.old_end.line + self.new_end.line,
+            character,
+        }
+    }
+
+    /// Move `range` to where its text is after the edit, or return `false` if it was replaced
+    fn update(&self, range: &mut Range) -> bool {
+        if self.contains(range) {
+            return false;
+        }
+
+        range.start = self.shift(range.start);
+        range.end = self.shift(range.end);
+        true
+    }
+
+    fn update_item(&self, item: &mut ItemData) -> bool {
+        if !self.update(&mut item.range) {
+            return false;
+        }
+
+        self.update(&mut item.name_range);
+        for child in &mut item.children {
+            self.update(&mut child.range);
+            self.update(&mut child.name_range);
+        }
+        true
+    }
+}
+
+/// Rescan the top level items of `file` that an edit changed, given its reparsed `ast` and the
+/// range of the new text in it. The items before and after the edit that parsed the same keep what
+/// was recorded for them, with the ranges after the edit moved along. Returns `false` without
+/// changing anything if no item was left as it was, since the whole file has to be rescanned then.
+pub fn rescan_edited(
+    db: &mut Database,
+    file: &FileUrl,
+    index: &PositionIndex,
+    ast: &Parse<SourceFile>,
+    edited: TextRange,
+) -> bool {
+    let file_data = db.files.get(file).expect("file data missing");
+    let old_root = file_data.ast.syntax_node();
+    let new_root = ast.syntax_node();
+    let old_items = old_root.children().collect::<Vec<_>>();
+    let new_items = new_root.children().collect::<Vec<_>>();
+
+    // Everything after the edit is the same text as before, just moved
+    let old_len = old_root.text_range().end();
+    let new_len = new_root.text_range().end();
+    let edited_old_end = edited.end() + old_len - new_len;
+    let is_same = |old: &SyntaxNode, new: &SyntaxNode, moved: Option<TextRange>| {
+        old.kind() == new.kind() && moved == Some(new.text_range())
+    };
+
+    let prefix = old_items
+        .iter()
+        .zip(&new_items)
+        .take_while(|(old, new)| {
+            old.text_range().end() < edited.start() && is_same(old, new, Some(old.text_range()))
+        })
+        .count();
+    let suffix = old_items[prefix..]
+        .iter()
+        .rev()
+        .zip(new_items[prefix..].iter().rev())
+        .take_while(|(old, new)| {
+            let moved = (old.text_range() + new_len).checked_sub(old_len);
+            old.text_range().start() > edited_old_end && is_same(old, new, moved)
+        })
+        .count();
+    if prefix == 0 && suffix == 0 {
+        return false;
+    }
+
+    let start = match prefix {
+        0 => TextSize::from(0),
+        _ => old_items[prefix - 1].text_range().end(),
+    };
+    let end = |items: &[SyntaxNode], len| {
+        items
+            .get(items.len() - suffix)
+            .map_or(len, |item| item.text_range().start())
+    };
+    let region = Region {
+        start: file_data.index.position(start),
+        old_end: file_data.index.position(end(&old_items, old_len)),
+        new_end: index.position(end(&new_items, new_len)),
+    };
+
+    forget_replaced(db, file, &region);
+    scan_items(
+        db,
+        file,
+        index,
+        &new_items[prefix..new_items.len() - suffix],
+    );
+    true
+}
+
+/// Remove everything recorded for what's inside `region` from the database, and move the rest of
+/// the file's ranges along with the edit
+fn forget_replaced(db: &mut Database, file: &FileUrl, region: &Region) {
+    let file_data = db.files.get_mut(file).expect("file data missing");
+    file_data
+        .imports
+        .retain_mut(|import| region.update(&mut import.range));
+    file_data
+        .impls
+        .retain_mut(|impl_| region.update(&mut impl_.range));
+
+    let (removed_includes, mut includes): (Vec<_>, Vec<_>) = std::mem::take(&mut file_data.modules)
+        .into_iter()
+        .partition(|include| region.contains(&include.range));
+    for include in &mut includes {
+        region.update(&mut include.range);
+        region.update(&mut include.name_range);
+    }
+    file_data.modules = includes;
+
+    let module_defs = std::mem::take(&mut file_data.module_defs);
+    let items = std::mem::take(&mut file_data.items)
+        .into_iter()
+        .collect::<HashSet<_>>();
+    let owners = std::mem::take(&mut file_data.associated_owners);
+    let names = std::mem::take(&mut file_data.name_refs)
+        .into_iter()
+        .collect::<HashSet<_>>();
+    let symbols = std::mem::take(&mut file_data.symbols);
+
+    for include in removed_includes {
+        let file_data = &db.files[file];
+        for path in &include.paths {
+            let still_included = file_data
+                .modules
+                .iter()
+                .any(|other| other.paths.contains(path));
+            if still_included {
+                continue;
+            }
+            if let Some(declaring) = db.module_files.get_mut(path) {
+                declaring.retain(|declaring| declaring != file);
+                if declaring.is_empty() {
+                    db.module_files.remove(path);
+                }
+            }
+        }
+
+        let still_declared = file_data
+            .modules
+            .iter()
+            .any(|other| other.module == include.module);
+        if !still_declared
+            && let Some(parent) = include
+                .module
+                .parent()
+                .and_then(|parent| db.modules.get_mut(&parent))
+        {
+            parent.children.retain(|child| child != &include.name);
+        }
+    }
+
+    let module_defs = module_defs
+        .into_iter()
+        .filter(|module| {
+            let Some(data) = db.modules.get_mut(module) else {
+                return false;
+            };
+            if &data.file_path != file || region.update(&mut data.range) {
+                return true;
+            }
+            db.modules.remove(module);
+            false
+        })
+        .collect();
+
+    let items = items
+        .into_iter()
+        .filter(|item| {
+            let Some(defs) = db.items.get_mut(item) else {
+                return false;
+            };
+            defs.retain_mut(|def| &def.file_path != file || region.update_item(def));
+            let kept = defs.iter().any(|def| &def.file_path == file);
+            if defs.is_empty() {
+                db.items.remove(item);
+                if let Some(module) = db.modules.get_mut(&item.module) {
+                    module.items.retain(|name| name != &item.name);
+                }
+            }
+            kept
+        })
+        .collect::<Vec<ItemPath>>();
+
+    let owners = owners
+        .into_iter()
+        .filter(|owner| {
+            let Some(associated_items) = db.associated_items.get_mut(owner) else {
+                return false;
+            };
+            associated_items
+                .retain_mut(|item| &item.file_path != file || region.update(&mut item.range));
+            for item in associated_items.iter_mut() {
+                if &item.file_path == file {
+                    region.update(&mut item.name_range);
+                }
+            }
+            let kept = associated_items.iter().any(|item| &item.file_path == file);
+            if associated_items.is_empty() {
+                db.associated_items.remove(owner);
+            }
+            kept
+        })
+        .collect();
+
+    let names = names
+        .into_iter()
+        .filter(|name| {
+            let Some(name_refs) = db.name_refs.get_mut(name) else {
+                return false;
+            };
+            name_refs.retain_mut(|name_ref| {
+                &name_ref.file_path != file || region.update(&mut name_ref.range)
+            });
+            let kept = name_refs.iter().any(|name_ref| &name_ref.file_path == file);
+            if name_refs.is_empty() {
+                db.name_refs.remove(name);
+            }
+            kept
+        })
+        .collect();
+
+    let symbols = symbols
+        .into_iter()
+        .filter(|symbol| {
+            let kept = db
+                .symbols
+                .location_mut(*symbol)
+                .is_some_and(|location| region.update(&mut location.range));
+            if !kept {
+                db.symbols.remove(*symbol);
+            }
+            kept
+        })
+        .collect();
+
+    let file_data = db.files.get_mut(file).expect("file data missing");
+    file_data.module_defs = module_defs;
+    file_data.items = items;
+    file_data.associated_owners = owners;
+    file_data.name_refs = names;
+    file_data.symbols = symbols;
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::TextDocumentContentChangeEvent;
+
+    use super::*;
+    use crate::database::{ImplData, ModulePath};
+    use crate::test_utils::{self, TempDir};
+    use crate::utils::PositionEncoding;
+
+    const TEXT: &str = "use std::fmt::{self, Display};
+
+#[derive(Debug, Clone)]
+pub struct Point { x: u8, y: u8 }
+
+impl Display for Point {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, \"{}\", self.x) }
+}
+
+mod inner {
+    pub enum Shape { Dot, Line }
+    pub fn area() -> u8 { 0 }
+}
+
+mod other;
+
+fn main() { let p = Point { x: 1, y: 2 }; inner::area(); } fn after() {}
+";
+
+    /// Changes replacing the first occurrence of each text with its replacement in turn, with
+    /// ranges into the text as it is after the changes before them
+    fn changes(text: &mut String, edits: &[(&str, &str)]) -> Vec<TextDocumentContentChangeEvent> {
+        let mut changes = vec![];
+        for (old, new) in edits {
+            let start = text.find(old).unwrap();
+            let index = PositionIndex::new(text, PositionEncoding::Utf8);
+            let range = TextRange::at(TextSize::from(start as u32), TextSize::of(*old));
+            changes.push(TextDocumentContentChangeEvent {
+                range: Some(crate::utils::range(range, &index)),
+                range_length: None,
+                text: new.to_string(),
+            });
+            text.replace_range(start..start + old.len(), new);
+        }
+        changes
+    }
+
+    /// Everything recorded for `file`, sorted
+    fn recorded(db: &Database, file: &FileUrl) -> Vec<String> {
+        let data = &db.files[file];
+        let mut lines = vec![];
+        for item in data.items.iter().collect::<HashSet<_>>() {
+            lines.push(format!("{item:?} {:?}", db.items[item]));
+        }
+        for owner in &data.associated_owners {
+            lines.extend(
+                db.associated_items[owner]
+                    .iter()
+                    .map(|item| format!("{item:?}")),
+            );
+        }
+        for name in data.name_refs.iter().collect::<HashSet<_>>() {
+            lines.extend(
+                db.name_refs[name]
+                    .iter()
+                    .map(|name_ref| format!("{name} {name_ref:?}")),
+            );
+        }
+        for module in &data.module_defs {
+            let module_data = &db.modules[module];
+            let mut children = module_data.children.clone();
+            let mut items = module_data.items.clone();
+            children.sort();
+            items.sort();
+            lines.push(format!(
+                "{module:?} {:?} {children:?} {items:?}",
+                module_data.range
+            ));
+        }
+        lines.extend(
+            db.symbols
+                .search("")
+                .iter()
+                .filter(|symbol| &symbol.location.uri == file.url())
+                .map(|symbol| format!("{symbol:?}")),
+        );
+        lines.extend(data.imports.iter().map(|import| format!("{import:?}")));
+        lines.extend(data.impls.iter().map(|impl_| format!("{impl_:?}")));
+        lines.extend(data.modules.iter().map(|include| format!("{include:?}")));
+        lines.extend(db.module_files.iter().map(|files| format!("{files:?}")));
+        lines.sort();
+        lines
+    }
+
+    /// Apply each step's edits to a file in turn, checking that what's recorded for it afterwards
+    /// is what loading the edited text records
+    fn assert_rescans_like_loading(name: &str, steps: &[&[(&str, &str)]]) {
+        let dir = TempDir::new(
+            name,
+            &[("lib.rs", TEXT), ("other.rs", "pub fn other() {}\n")],
+        );
+        let file = FileUrl::from_path(&dir.0.join("lib.rs")).unwrap();
+        let (mut db, _client) = test_utils::database();
+        db.load_file(&file).unwrap();
+
+        let mut text = TEXT.to_string();
+        for (version, edits) in steps.iter().enumerate() {
+            let changes = changes(&mut text, edits);
+            db.edit_file(&file, version as i32 + 1, changes).unwrap();
+            assert_eq!(db.files[&file].text, text);
+
+            std::fs::write(file.path(), &text).unwrap();
+            let (mut loaded, _loaded_client) = test_utils::database();
+            loaded.load_file(&file).unwrap();
+            assert_eq!(recorded(&db, &file), recorded(&loaded, &file), "{text}");
+        }
+    }
+
+    #[test]
+    fn edits_move_the_items_after_them() {
+        assert_rescans_like_loading(
+            "incremental-lines",
+            &[
+                &[("{ 0 }", "{\n        1 + 1\n    }")],
+                &[("Dot, Line", "Dot")],
+            ],
+        );
+        // `after` is on the same line as `main`
+        assert_rescans_like_loading("incremental-columns", &[&[("let p", "let point")]]);
+        assert_rescans_like_loading(
+            "incremental-whitespace",
+            &[&[("\n\nmod inner", "\n\n\n\nmod inner")]],
+        );
+    }
+
+    #[test]
+    fn edits_add_and_remove_items() {
+        assert_rescans_like_loading(
+            "incremental-items",
+            &[
+                &[
+                    (
+                        "#[derive(Debug, Clone)]\npub struct Point { x: u8, y: u8 }\n",
+                        "",
+                    ),
+                    ("fn main", "mod added { pub fn f() {} }\n\nfn main"),
+                ],
+                &[("mod added { pub fn f() {} }\n\n", "")],
+            ],
+        );
+    }
+
+    #[test]
+    fn unclosed_braces_swallow_the_items_after_them_until_closed() {
+        assert_rescans_like_loading(
+            "incremental-braces",
+            &[
+                &[("mod inner {", "fn broken() {\n\nmod inner {")],
+                &[("fn broken() {", "fn broken() {}")],
+            ],
+        );
+    }
+
+    #[test]
+    fn untouched_items_keep_what_was_recorded_for_them() {
+        let dir = TempDir::new(
+            "incremental-untouched",
+            &[("lib.rs", TEXT), ("other.rs", "pub fn other() {}\n")],
+        );
+        let file = FileUrl::from_path(&dir.0.join("lib.rs")).unwrap();
+        let (mut db, _client) = test_utils::database();
+        db.load_file(&file).unwrap();
+
+        // Only a full rescan would drop something recorded for `mod other;`
+        let data = db.files.get_mut(&file).unwrap();
+        let range = data
+            .modules
+            .iter()
+            .find(|include| include.name == "other")
+            .unwrap()
+            .range;
+        data.impls.push(ImplData {
+            trait_: Some(vec!["Marker".into()]),
+            self_ty: None,
+            module: ModulePath::crate_root(),
+            range,
+        });
+
+        let mut text = TEXT.to_string();
+        let changes = changes(&mut text, &[("{ 0 }", "{\n        1 + 1\n    }")]);
+        db.edit_file(&file, 1, changes).unwrap();
+
+        let marker = db.files[&file]
+            .impls
+            .iter()
+            .find(|impl_| impl_.trait_ == Some(vec!["Marker".into()]))
+            .unwrap();
+        assert_eq!(marker.range.start.line, range.start.line + 2);
+    }
+}
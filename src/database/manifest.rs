@@ -0,0 +1,718 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+use rust_analyzer_syntax::Edition;
+use snafu::{OptionExt, ResultExt, Whatever};
+use toml::Value;
+
+use crate::database::Database;
+use crate::database::file::should_skip_dir;
+use crate::utils::OrLog;
+
+/// A package read from its `Cargo.toml`
+#[derive(Debug)]
+pub struct Package {
+    pub name: String,
+    /// The canonicalized directory the `Cargo.toml` is in
+    pub manifest_dir: PathBuf,
+    /// The edition every file in the package is parsed with
+    pub edition: Edition,
+    pub targets: Vec<Target>,
+    /// The names this package's crates refer to their dependencies by, mapped to the dependencies'
+    /// package names. Normal, dev and build dependencies are all lumped together.
+    pub dependencies: HashMap<String, String>,
+}
+
+/// A crate built from a package
+#[derive(Debug)]
+pub struct Target {
+    /// The crate's name, as it's referred to in code
+    pub name: String,
+    pub kind: TargetKind,
+    /// The crate root file, canonicalized when it exists
+    pub root: PathBuf,
+    /// What the crate's modules are stored under in [`ModulePath`](crate::database::ModulePath).
+    /// Binaries are usually named after their package like its library is, so everything but the
+    /// library has its kind appended to tell them apart.
+    pub crate_: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TargetKind {
+    Lib,
+    Bin,
+    Example,
+    Test,
+    Bench,
+    BuildScript,
+}
+
+impl Display for TargetKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            TargetKind::Lib => "lib",
+            TargetKind::Bin => "bin",
+            TargetKind::Example => "example",
+            TargetKind::Test => "test",
+            TargetKind::Bench => "bench",
+            TargetKind::BuildScript => "build",
+        })
+    }
+}
+
+impl Target {
+    fn new(name: &str, kind: TargetKind, root: PathBuf) -> Target {
+        let name = name.replace('-', "_");
+        let crate_ = match kind {
+            TargetKind::Lib => name.clone(),
+            _ => format!("{name}-{kind}"),
+        };
+        Target {
+            name,
+            kind,
+            root: root.canonicalize().unwrap_or(root),
+            crate_,
+        }
+    }
+}
+
+/// Load the packages in `root`: the package or workspace described by its `Cargo.toml`, or if it
+/// doesn't have one, those of its subdirectories
+pub fn find_packages(db: &mut Database, root: &Path) -> Result<(), Whatever> {
+    if root.join("Cargo.toml").is_file() {
+        return load_manifest(db, root);
+    }
+
+    let entries = std::fs::read_dir(root)
+        .with_whatever_context(|_| format!("failed to load path {root:?}"))?
+        .filter_map(|entry| entry.ok())
+        .collect::<Vec<_>>();
+    if entries.iter().any(should_skip_dir) {
+        return Ok(());
+    }
+
+    for entry in entries {
+        let is_dir = entry.file_type().is_ok_and(|ty| ty.is_dir());
+        if is_dir && entry.file_name() != OsStr::new(".git") {
+            _ = find_packages(db, &entry.path()).or_log(db);
+        }
+    }
+
+    Ok(())
+}
+
+/// The package the file at `path` belongs to; the one whose directory contains it most closely
+pub fn package_of<'db>(db: &'db Database, path: &Path) -> Option<&'db Package> {
+    db.packages
+        .iter()
+        .filter(|package| path.starts_with(&package.manifest_dir))
+        .max_by_key(|package| package.manifest_dir.components().count())
+}
+
+/// The package and target whose crate is stored under `crate_`
+pub fn find_target<'db>(db: &'db Database, crate_: &str) -> Option<(&'db Package, &'db Target)> {
+    db.packages.iter().find_map(|package| {
+        let target = package
+            .targets
+            .iter()
+            .find(|target| target.crate_ == crate_)?;
+        Some((package, target))
+    })
+}
+
+/// The package and target whose crate root is the file at `path`
+pub fn find_target_by_root<'db>(
+    db: &'db Database,
+    path: &Path,
+) -> Option<(&'db Package, &'db Target)> {
+    db.packages.iter().find_map(|package| {
+        let target = package.targets.iter().find(|target| target.root == path)?;
+        Some((package, target))
+    })
+}
+
+/// The crate root that `crate_` refers to as `name`, through its package's dependencies. Every
+/// target but the library itself can also refer to the package's library.
+pub fn dependency_crate(db: &Database, crate_: &str, name: &str) -> Option<String> {
+    let (package, target) = find_target(db, crate_)?;
+    if let Some(lib) = own_library(package, target)
+        && lib.name == name
+    {
+        return Some(lib.crate_.clone());
+    }
+
+    let dependency = package.dependencies.get(name)?;
+    library_crate(db, dependency)
+}
+
+/// The name `crate_` refers to `dependency` (a library crate) by, if it depends on it at all
+pub fn dependency_name(db: &Database, crate_: &str, dependency: &str) -> Option<String> {
+    let (package, target) = find_target(db, crate_)?;
+    if let Some(lib) = own_library(package, target)
+        && lib.crate_ == dependency
+    {
+        return Some(lib.name.clone());
+    }
+
+    package
+        .dependencies
+        .iter()
+        .find(|(_, package)| library_crate(db, package).as_deref() == Some(dependency))
+        .map(|(name, _)| name.clone())
+}
+
+/// The library of `package`, unless `target` is that library
+fn own_library<'package>(package: &'package Package, target: &Target) -> Option<&'package Target> {
+    package
+        .targets
+        .iter()
+        .find(|lib| lib.kind == TargetKind::Lib && lib.crate_ != target.crate_)
+}
+
+fn library_crate(db: &Database, package: &str) -> Option<String> {
+    db.packages
+        .iter()
+        .filter(|candidate| candidate.name == package)
+        .flat_map(|package| &package.targets)
+        .find(|target| target.kind == TargetKind::Lib)
+        .map(|target| target.crate_.clone())
+}
+
+/// Load the `Cargo.toml` in `dir`, along with every member if it's a workspace root
+fn load_manifest(db: &mut Database, dir: &Path) -> Result<(), Whatever> {
+    let dir = dir
+        .canonicalize()
+        .with_whatever_context(|_| format!("failed to canonicalize path {dir:?}"))?;
+    if db
+        .packages
+        .iter()
+        .any(|package| package.manifest_dir == dir)
+    {
+        return Ok(());
+    }
+    let manifest = read_manifest(&dir)?;
+
+    if let Some(workspace) = lookup(&manifest, &["workspace"]) {
+        for member in workspace_members(&dir, workspace) {
+            if member != dir {
+                _ = load_manifest(db, &member).or_log(db);
+            }
+        }
+    }
+
+    if lookup(&manifest, &["package"]).is_some() {
+        // Members can inherit fields and dependencies from their workspace
+        let workspace = match lookup(&manifest, &["workspace"]) {
+            Some(_) => Some((dir.clone(), manifest.clone())),
+            None => find_workspace(&dir),
+        };
+        load_package(db, &dir, &manifest, workspace.as_ref())?;
+    }
+
+    Ok(())
+}
+
+fn read_manifest(dir: &Path) -> Result<Value, Whatever> {
+    let path = dir.join("Cargo.toml");
+    let src = std::fs::read_to_string(&path)
+        .with_whatever_context(|_| format!("failed to read {path:?}"))?;
+    let table = src
+        .parse::<toml::Table>()
+        .with_whatever_context(|_| format!("failed to parse {path:?}"))?;
+    Ok(Value::Table(table))
+}
+
+/// Look up a path of keys through nested tables
+fn lookup<'value>(value: &'value Value, keys: &[&str]) -> Option<&'value Value> {
+    keys.iter()
+        .try_fold(value, |value, key| value.as_table()?.get(*key))
+}
+
+/// The closest workspace root above `dir`
+fn find_workspace(dir: &Path) -> Option<(PathBuf, Value)> {
+    dir.ancestors().skip(1).find_map(|ancestor| {
+        if !ancestor.join("Cargo.toml").is_file() {
+            return None;
+        }
+        let manifest = read_manifest(ancestor).ok()?;
+        lookup(&manifest, &["workspace"])
+            .is_some()
+            .then(|| (ancestor.to_path_buf(), manifest))
+    })
+}
+
+fn load_package(
+    db: &mut Database,
+    dir: &Path,
+    manifest: &Value,
+    workspace: Option<&(PathBuf, Value)>,
+) -> Result<(), Whatever> {
+    let (package, dependency_dirs) = read_package(dir, manifest, workspace)?;
+    db.packages.push(package);
+
+    // Path dependencies outside of the workspace are still part of the project
+    for dependency_dir in dependency_dirs {
+        _ = load_manifest(db, &dependency_dir).or_log(db);
+    }
+
+    Ok(())
+}
+
+/// The package described by `manifest`, which is in `dir`, along with the directories of its path
+/// dependencies
+fn read_package(
+    dir: &Path,
+    manifest: &Value,
+    workspace: Option<&(PathBuf, Value)>,
+) -> Result<(Package, Vec<PathBuf>), Whatever> {
+    let package = lookup(manifest, &["package"]).expect("manifest was checked to have a package");
+    let name = lookup(package, &["name"])
+        .and_then(Value::as_str)
+        .with_whatever_context(|| format!("package in {dir:?} has no name"))?
+        .to_string();
+
+    let inherited = |key: &str| match lookup(package, &[key, "workspace"]) {
+        Some(Value::Boolean(true)) => lookup(&workspace?.1, &["workspace", "package", key]),
+        _ => lookup(package, &[key]),
+    };
+    let edition = match inherited("edition").and_then(Value::as_str) {
+        Some(edition) => edition
+            .parse()
+            .with_whatever_context(|_| format!("package `{name}` has an invalid edition"))?,
+        None => Edition::DEFAULT,
+    };
+
+    let mut dependencies = HashMap::new();
+    let mut dependency_dirs = vec![];
+    for (dependency, spec) in dependency_tables(manifest) {
+        let (spec, base) = match lookup(spec, &["workspace"]) {
+            Some(Value::Boolean(true)) => {
+                let Some((workspace_dir, workspace)) = workspace else {
+                    continue;
+                };
+                let Some(spec) = lookup(workspace, &["workspace", "dependencies", dependency])
+                else {
+                    continue;
+                };
+                (spec, workspace_dir.as_path())
+            }
+            _ => (spec, dir),
+        };
+        let package_name = lookup(spec, &["package"])
+            .and_then(Value::as_str)
+            .unwrap_or(dependency);
+        dependencies.insert(dependency.replace('-', "_"), package_name.to_string());
+        if let Some(path) = lookup(spec, &["path"]).and_then(Value::as_str) {
+            dependency_dirs.push(base.join(path));
+        }
+    }
+
+    let targets = package_targets(dir, &name, manifest);
+    let package = Package {
+        name,
+        manifest_dir: dir.to_path_buf(),
+        edition,
+        targets,
+        dependencies,
+    };
+    Ok((package, dependency_dirs))
+}
+
+/// Every dependency declared in `manifest`, including target-specific ones, with its specification
+fn dependency_tables(manifest: &Value) -> impl Iterator<Item = (&str, &Value)> {
+    const KINDS: [&str; 3] = ["dependencies", "dev-dependencies", "build-dependencies"];
+
+    let platforms = lookup(manifest, &["target"])
+        .and_then(Value::as_table)
+        .into_iter()
+        .flat_map(|targets| targets.values());
+    std::iter::once(manifest)
+        .chain(platforms)
+        .flat_map(|table| KINDS.iter().filter_map(|kind| lookup(table, &[kind])))
+        .filter_map(Value::as_table)
+        .flatten()
+        .map(|(name, spec)| (name.as_str(), spec))
+}
+
+/// The targets declared in the manifest, plus those Cargo discovers by itself: `src/lib.rs`,
+/// `src/main.rs`, `build.rs`, and every `foo.rs` or `foo/main.rs` in `src/bin`, `examples`,
+/// `tests` and `benches`
+fn package_targets(dir: &Path, package_name: &str, manifest: &Value) -> Vec<Target> {
+    let mut targets = vec![];
+
+    let default_lib = dir.join("src/lib.rs");
+    match lookup(manifest, &["lib"]) {
+        Some(lib) => {
+            let name = lookup(lib, &["name"])
+                .and_then(Value::as_str)
+                .unwrap_or(package_name);
+            let root = lookup(lib, &["path"])
+                .and_then(Value::as_str)
+                .map_or(default_lib, |path| dir.join(path));
+            targets.push(Target::new(name, TargetKind::Lib, root));
+        }
+        None if default_lib.is_file() => {
+            targets.push(Target::new(package_name, TargetKind::Lib, default_lib));
+        }
+        None => {}
+    }
+
+    let sections = [
+        ("bin", TargetKind::Bin, "src/bin"),
+        ("example", TargetKind::Example, "examples"),
+        ("test", TargetKind::Test, "tests"),
+        ("bench", TargetKind::Bench, "benches"),
+    ];
+    for (section, kind, default_dir) in sections {
+        let declared = lookup(manifest, &[section])
+            .and_then(Value::as_array)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        for target in declared {
+            let Some(name) = lookup(target, &["name"]).and_then(Value::as_str) else {
+                continue;
+            };
+            let root = match lookup(target, &["path"]).and_then(Value::as_str) {
+                Some(path) => dir.join(path),
+                None if kind == TargetKind::Bin && name == package_name => dir.join("src/main.rs"),
+                None => {
+                    let file = dir.join(default_dir).join(format!("{name}.rs"));
+                    match file.is_file() {
+                        true => file,
+                        false => dir.join(default_dir).join(name).join("main.rs"),
+                    }
+                }
+            };
+            targets.push(Target::new(name, kind, root));
+        }
+
+        let auto = lookup(manifest, &["package", &format!("auto{section}s")])
+            .is_none_or(|auto| auto != &Value::Boolean(false));
+        if !auto {
+            continue;
+        }
+
+        let mut discovered = discover_targets(&dir.join(default_dir));
+        if kind == TargetKind::Bin {
+            discovered.push((package_name.to_string(), dir.join("src/main.rs")));
+        }
+        for (name, root) in discovered {
+            // Declared targets can rename or move discovered ones
+            let target = Target::new(&name, kind, root);
+            let is_declared = targets.iter().any(|declared| {
+                declared.kind == kind
+                    && (declared.name == target.name || declared.root == target.root)
+            });
+            if target.root.is_file() && !is_declared {
+                targets.push(target);
+            }
+        }
+    }
+
+    let build = match lookup(manifest, &["package", "build"]) {
+        Some(Value::String(path)) => Some(dir.join(path)),
+        Some(_) => None,
+        None => Some(dir.join("build.rs")).filter(|path| path.is_file()),
+    };
+    if let Some(build) = build {
+        targets.push(Target::new(package_name, TargetKind::BuildScript, build));
+    }
+
+    targets
+}
+
+/// The `foo.rs` files and `foo/main.rs` files in `dir`, with their target names
+fn discover_targets(dir: &Path) -> Vec<(String, PathBuf)> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return vec![];
+    };
+
+    let mut targets = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let path = entry.path();
+            if path.is_dir() {
+                let main = path.join("main.rs");
+                let name = entry.file_name().to_string_lossy().into_owned();
+                return main.is_file().then_some((name, main));
+            }
+
+            if path.extension() != Some(OsStr::new("rs")) {
+                return None;
+            }
+            let name = path.file_stem()?.to_string_lossy().into_owned();
+            Some((name, path))
+        })
+        .collect::<Vec<_>>();
+    targets.sort();
+    targets
+}
+
+/// The member directories of a workspace rooted in `dir`. Members can be globs, and anything
+/// under an excluded path is left out.
+fn workspace_members(dir: &Path, workspace: &Value) -> Vec<PathBuf> {
+    let paths = |key: &str| {
+        lookup(workspace, &[key])
+            .and_then(Value::as_array)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(Value::as_str)
+            .collect::<Vec<_>>()
+    };
+    let excluded = paths("exclude")
+        .into_iter()
+        .map(|path| dir.join(path))
+        .collect::<Vec<_>>();
+
+    let mut members = paths("members")
+        .into_iter()
+        .chain(paths("default-members"))
+        .flat_map(|pattern| expand_glob(dir, pattern))
+        .filter(|member| member.join("Cargo.toml").is_file())
+        .filter(|member| !excluded.iter().any(|excluded| member.starts_with(excluded)))
+        .collect::<Vec<_>>();
+    members.sort();
+    members.dedup();
+    members
+}
+
+/// The directories matching `pattern` relative to `dir`, where segments may contain `*` and `?`
+fn expand_glob(dir: &Path, pattern: &str) -> Vec<PathBuf> {
+    let mut matches = vec![dir.to_path_buf()];
+    for segment in pattern.split('/').filter(|segment| !segment.is_empty()) {
+        if !segment.contains(['*', '?']) {
+            matches.iter_mut().for_each(|path| path.push(segment));
+            continue;
+        }
+
+        matches = matches
+            .iter()
+            .filter_map(|path| std::fs::read_dir(path).ok())
+            .flatten()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_ok_and(|ty| ty.is_dir()))
+            .filter(|entry| glob_matches(segment, &entry.file_name().to_string_lossy()))
+            .map(|entry| entry.path())
+            .collect();
+    }
+
+    matches
+}
+
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    match pattern.chars().next() {
+        None => name.is_empty(),
+        Some('*') => {
+            let rest = &pattern[1..];
+            name.char_indices()
+                .map(|(i, _)| i)
+                .chain([name.len()])
+                .any(|i| glob_matches(rest, &name[i..]))
+        }
+        Some(c) => {
+            let mut chars = name.chars();
+            match chars.next() {
+                Some(n) if c == '?' || c == n => {
+                    glob_matches(&pattern[c.len_utf8()..], chars.as_str())
+                }
+                _ => false,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    fn manifest(src: &str) -> Value {
+        Value::Table(src.parse().unwrap())
+    }
+
+    fn targets(package: &Package) -> Vec<(TargetKind, &str, PathBuf)> {
+        let dir = &package.manifest_dir;
+        let mut targets = package
+            .targets
+            .iter()
+            .map(|target| {
+                let root = target.root.strip_prefix(dir).unwrap_or(&target.root);
+                (target.kind, target.crate_.as_str(), root.to_path_buf())
+            })
+            .collect::<Vec<_>>();
+        targets.sort_by(|a, b| a.1.cmp(b.1));
+        targets
+    }
+
+    #[test]
+    fn glob_matches_wildcards() {
+        assert!(glob_matches("*", "core"));
+        assert!(glob_matches("*", ""));
+        assert!(glob_matches("crate-*", "crate-core"));
+        assert!(glob_matches("c?re", "core"));
+        assert!(glob_matches("*-*-lib", "a-b-lib"));
+        assert!(glob_matches("é*", "éa"));
+        assert!(!glob_matches("crate-*", "core"));
+        assert!(!glob_matches("c?re", "cre"));
+        assert!(!glob_matches("core", "core2"));
+    }
+
+    #[test]
+    fn workspace_members_expand_globs_and_skip_excluded() {
+        let dir = TempDir::new(
+            "members",
+            &[
+                ("crates/a/Cargo.toml", ""),
+                ("crates/b/Cargo.toml", ""),
+                ("crates/old/Cargo.toml", ""),
+                ("crates/notes/README.md", ""),
+                ("tools/cli/Cargo.toml", ""),
+            ],
+        );
+        let workspace = manifest(
+            r#"
+            members = ["crates/*", "tools/cli", "missing"]
+            exclude = ["crates/old"]
+            "#,
+        );
+
+        let members = workspace_members(&dir.0, &workspace)
+            .into_iter()
+            .map(|member| member.strip_prefix(&dir.0).unwrap().to_path_buf())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            members,
+            ["crates/a", "crates/b", "tools/cli"].map(PathBuf::from)
+        );
+        assert_eq!(expand_glob(&dir.0, "crates/?").len(), 2);
+    }
+
+    #[test]
+    fn read_package_inherits_from_the_workspace() {
+        let dir = TempDir::new("inherit", &[("member/src/lib.rs", "")]);
+        let workspace = manifest(
+            r#"
+            [workspace]
+            members = ["member"]
+            package.edition = "2021"
+
+            [workspace.dependencies]
+            serde = "1"
+            local = { path = "crates/local", package = "local-impl" }
+            "#,
+        );
+        let member = manifest(
+            r#"
+            [package]
+            name = "my-member"
+            edition.workspace = true
+
+            [dependencies]
+            serde.workspace = true
+            local = { workspace = true }
+            renamed = { version = "0.1", package = "original" }
+
+            [target.'cfg(unix)'.dev-dependencies]
+            'unix-only' = "1"
+            "#,
+        );
+
+        let workspace = (dir.0.clone(), workspace);
+        let (package, dependency_dirs) =
+            read_package(&dir.0.join("member"), &member, Some(&workspace)).unwrap();
+        assert_eq!(package.name, "my-member");
+        assert_eq!(package.edition, Edition::Edition2021);
+        assert_eq!(package.dependencies["serde"], "serde");
+        assert_eq!(package.dependencies["local"], "local-impl");
+        assert_eq!(package.dependencies["renamed"], "original");
+        assert_eq!(package.dependencies["unix_only"], "unix-only");
+        assert_eq!(dependency_dirs, [dir.0.join("crates/local")]);
+        assert_eq!(
+            targets(&package),
+            [(TargetKind::Lib, "my_member", PathBuf::from("src/lib.rs"))]
+        );
+    }
+
+    #[test]
+    fn read_package_defaults_to_2015_and_requires_a_name() {
+        let dir = TempDir::new("edition", &[]);
+        let (package, _) = read_package(&dir.0, &manifest("package.name = 'old'"), None).unwrap();
+        assert_eq!(package.edition, Edition::Edition2015);
+        assert!(read_package(&dir.0, &manifest("package.edition = '2021'"), None).is_err());
+    }
+
+    #[test]
+    fn package_targets_combine_declared_and_discovered_targets() {
+        let dir = TempDir::new(
+            "targets",
+            &[
+                ("src/lib.rs", ""),
+                ("src/main.rs", ""),
+                ("src/bin/tool.rs", ""),
+                ("src/bin/multi/main.rs", ""),
+                ("src/server.rs", ""),
+                ("examples/demo.rs", ""),
+                ("tests/it.rs", ""),
+                ("build.rs", ""),
+            ],
+        );
+        let manifest = manifest(
+            r#"
+            [package]
+            name = "app"
+            autotests = false
+
+            [lib]
+            name = "app_core"
+
+            [[bin]]
+            name = "server"
+            path = "src/server.rs"
+
+            [bin.metadata]
+            note = """
+            belongs to the last [[bin]]"""
+
+            [[bin]]
+            name = 'tool'
+            "#,
+        );
+
+        let (package, _) = read_package(&dir.0, &manifest, None).unwrap();
+        assert_eq!(
+            targets(&package),
+            [
+                (TargetKind::Bin, "app-bin", PathBuf::from("src/main.rs")),
+                (
+                    TargetKind::BuildScript,
+                    "app-build",
+                    PathBuf::from("build.rs")
+                ),
+                (TargetKind::Lib, "app_core", PathBuf::from("src/lib.rs")),
+                (
+                    TargetKind::Example,
+                    "demo-example",
+                    PathBuf::from("examples/demo.rs")
+                ),
+                (
+                    TargetKind::Bin,
+                    "multi-bin",
+                    PathBuf::from("src/bin/multi/main.rs")
+                ),
+                (
+                    TargetKind::Bin,
+                    "server-bin",
+                    PathBuf::from("src/server.rs")
+                ),
+                (
+                    TargetKind::Bin,
+                    "tool-bin",
+                    PathBuf::from("src/bin/tool.rs")
+                ),
+            ]
+        );
+    }
+}
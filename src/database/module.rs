@@ -1,10 +1,196 @@
-use crate::database::{Database, FileUrl};
+use std::path::{Path, PathBuf};
+
+use rust_analyzer_syntax::ast::{self, HasAttrs, HasName};
+use rust_analyzer_syntax::{AstNode, AstToken, SyntaxKind};
+
+use crate::database::manifest::{find_target_by_root, package_of};
+use crate::database::{Database, FileUrl, ModulePath};
 
 pub fn scan_file_modules(db: &mut Database, file: &FileUrl) {
-    db.files
+    let parent = get_parent_uri(db, file);
+    let module_path = get_module_path(db, file, parent.as_ref());
+
+    let file_data = db
+        .files
         .get_mut(file)
-        .expect("failed to access file during update")
-        .parent = get_parent_uri(db, file);
+        .expect("failed to access file during update");
+    file_data.parent = parent;
+    file_data.module_path = module_path;
+}
+
+/// The module path of a file is its parent's module path followed by the file's module name, or the
+/// path of the module declaring it with a `#[path]` attribute. Files without a parent are crate
+/// roots, named after their Cargo target.
+fn get_module_path(db: &Database, file: &FileUrl, parent: Option<&FileUrl>) -> ModulePath {
+    let Some(parent_data) = parent.and_then(|parent| db.files.get(parent)) else {
+        return match find_target_by_root(db, file.path()) {
+            Some((_, target)) => ModulePath {
+                crate_: target.crate_.clone(),
+                segments: vec![],
+            },
+            None => ModulePath::crate_root(),
+        };
+    };
+
+    let include = parent_data.modules.iter().find(|include| {
+        include
+            .paths
+            .iter()
+            .any(|path| path.as_path() == file.path())
+    });
+    match include {
+        Some(include) => include.module.clone(),
+        None => parent_data.module_path.child(&get_module_name(file)),
+    }
+}
+
+/// The paths given by `#[path = "..."]` attributes on a `mod` declaration, including those behind
+/// `#[cfg_attr(.., path = "...")]`
+pub fn path_attributes(module: &ast::Module) -> Vec<String> {
+    let mut paths = vec![];
+    for attr in module.attrs() {
+        match attr.simple_name().as_deref() {
+            Some("path") => {
+                let value = attr
+                    .meta()
+                    .and_then(|meta| meta.expr())
+                    .and_then(|expr| expr.syntax().first_token())
+                    .and_then(ast::String::cast)
+                    .and_then(|string| Some(string.value().ok()?.into_owned()));
+                paths.extend(value);
+            }
+            Some("cfg_attr") => {
+                let Some(token_tree) = attr.meta().and_then(|meta| meta.token_tree()) else {
+                    continue;
+                };
+                // `path` isn't a cfg option, so any `path = "..."` is the attribute
+                let tokens = token_tree
+                    .syntax()
+                    .descendants_with_tokens()
+                    .filter_map(|element| element.into_token())
+                    .filter(|token| !token.kind().is_trivia())
+                    .collect::<Vec<_>>();
+                let values = tokens.windows(3).filter_map(|window| {
+                    let [key, eq, value] = window else {
+                        return None;
+                    };
+                    if key.text() != "path" || eq.kind() != SyntaxKind::EQ {
+                        return None;
+                    }
+                    Some(ast::String::cast(value.clone())?.value().ok()?.into_owned())
+                });
+                paths.extend(values);
+            }
+            _ => {}
+        }
+    }
+
+    paths
+}
+
+/// The files `module`, declared in `file`, can refer to: those its `#[path]` attributes point to,
+/// or otherwise `foo.rs` and `foo/mod.rs` in the directory of the module it's declared in
+pub fn declared_files(db: &Database, file: &FileUrl, module: &ast::Module) -> Vec<PathBuf> {
+    let paths = path_attributes(module);
+    let dir = declaration_dir(db, file, module, !paths.is_empty());
+    let paths = match paths.is_empty() {
+        true => {
+            let Some(name) = module.name().map(|name| name.to_string()) else {
+                return vec![];
+            };
+            vec![format!("{name}.rs"), format!("{name}/mod.rs")]
+        }
+        false => paths,
+    };
+
+    paths
+        .into_iter()
+        .map(|path| {
+            let path = dir.join(path);
+            path.canonicalize().unwrap_or(path)
+        })
+        .collect()
+}
+
+/// The directory the files of modules declared in `file` go in. It's the directory inline
+/// modules around `module` would have as files, below the declaring file's own directory, which is
+/// named after the file unless it's a `mod.rs`-like file. Inline modules can have `#[path]`
+/// attributes to rename their directory.
+///
+/// `#[path]` attributes outside of inline modules are relative to the declaring file's directory
+/// either way.
+fn declaration_dir(
+    db: &Database,
+    file: &FileUrl,
+    module: &ast::Module,
+    path_attribute: bool,
+) -> PathBuf {
+    let path = file.path();
+    let mut dir = path.parent().unwrap_or(Path::new("/")).to_path_buf();
+
+    let mut inline_modules = module
+        .syntax()
+        .ancestors()
+        .skip(1)
+        .filter_map(ast::Module::cast)
+        .collect::<Vec<_>>();
+    inline_modules.reverse();
+
+    let owns_dir = is_mod_rs(db, file) || (path_attribute && inline_modules.is_empty());
+    if !owns_dir && let Some(stem) = path.file_stem() {
+        dir.push(stem);
+    }
+    for inline_module in inline_modules {
+        match path_attributes(&inline_module).into_iter().next() {
+            Some(path) => dir.push(path),
+            None => dir.extend(inline_module.name().map(|name| name.to_string())),
+        }
+    }
+
+    dir
+}
+
+/// Whether the file owns its directory, so that the files of the modules it declares go straight
+/// inside it: `mod.rs` files, crate roots, and files included through `#[path]` attributes
+fn is_mod_rs(db: &Database, file: &FileUrl) -> bool {
+    let Some(data) = db.files.get(file) else {
+        return false;
+    };
+
+    file.path().file_name().is_some_and(|name| name == "mod.rs")
+        || data.module_path.segments.is_empty()
+        || is_path_included(db, file)
+}
+
+/// Whether the file is included through a `#[path]` attribute on its declaration
+pub fn is_path_included(db: &Database, file: &FileUrl) -> bool {
+    db.files
+        .get(file)
+        .and_then(|data| db.files.get(data.parent.as_ref()?))
+        .is_some_and(|parent| {
+            parent.modules.iter().any(|include| {
+                include.path_attribute
+                    && include
+                        .paths
+                        .iter()
+                        .any(|path| path.as_path() == file.path())
+            })
+        })
+}
+
+/// The name a file's module is declared as in its parent; the file stem, or the directory name
+/// for `mod.rs` files
+pub fn get_module_name(file: &FileUrl) -> String {
+    let path = file.path();
+    let name = if path.file_name().is_some_and(|name| name == "mod.rs") {
+        path.parent().and_then(|dir| dir.file_name())
+    } else {
+        path.file_stem()
+    };
+
+    name.expect("file had no filename")
+        .to_string_lossy()
+        .into_owned()
 }
 
 /// Check if an adjacent file named `mod.rs`, `lib.rs`, or `main.rs`,
@@ -12,16 +198,27 @@ pub fn scan_file_modules(db: &mut Database, file: &FileUrl) {
 /// and if so return its uri. The file will be loaded in the database if returned.
 ///
 /// If this file is a `mod.rs` file, then it will check from a directory up.
+///
+/// Files with a `mod` declaration that refers to them are checked for first, and get the declaring
+/// file; this is what finds files declared inside inline modules, or with `#[path]` attributes.
+///
+/// Cargo targets are crate roots, as are `lib.rs` and `main.rs` files outside of any package.
 fn get_parent_uri(db: &mut Database, file: &FileUrl) -> Option<FileUrl> {
     let path = file.path();
     let file_name = path.file_name().expect("file had no filename");
 
-    if file_name == "lib.rs" || file_name == "main.rs" {
+    if find_target_by_root(db, path).is_some() {
+        return None;
+    }
+    if let Some(parent) = declaring_file(db, file) {
+        return Some(parent);
+    }
+    if package_of(db, path).is_none() && (file_name == "lib.rs" || file_name == "main.rs") {
         return None;
     }
 
     let path = if file_name != "mod.rs" {
-        &path
+        path
     } else {
         path.parent()?
     };
@@ -60,3 +257,168 @@ fn get_parent_uri(db: &mut Database, file: &FileUrl) -> Option<FileUrl> {
 
     None
 }
+
+/// The file with a `mod` declaration referring to `file`. When there are several, like a package's
+/// `lib.rs` and `main.rs` both declaring `mod util;`, they're picked in the same order parents are
+/// guessed from the file layout.
+fn declaring_file(db: &Database, file: &FileUrl) -> Option<FileUrl> {
+    let rank = |parent: &FileUrl| match parent.path().file_name().and_then(|name| name.to_str()) {
+        Some("mod.rs") => 0,
+        Some("lib.rs") => 1,
+        Some("main.rs") => 2,
+        _ => 3,
+    };
+
+    db.module_files
+        .get(file.path())?
+        .iter()
+        .filter(|parent| !is_descendant(db, parent, file))
+        .min_by_key(|parent| (rank(parent), parent.path()))
+        .cloned()
+}
+
+/// Whether `file` is `ancestor` or one of the files below it, which `#[path]` attributes could
+/// otherwise make into a cycle
+fn is_descendant(db: &Database, file: &FileUrl, ancestor: &FileUrl) -> bool {
+    let mut current = Some(file);
+    // A cycle that's already there shouldn't hang the walk
+    for _ in 0..db.files.len() + 1 {
+        let Some(file) = current else {
+            return false;
+        };
+        if file == ancestor {
+            return true;
+        }
+        current = db.files.get(file).and_then(|data| data.parent.as_ref());
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_analyzer_syntax::{Edition, SourceFile};
+
+    use super::*;
+    use crate::database::FileData;
+    use crate::test_utils::{self, TempDir};
+
+    fn first_module(text: &str) -> ast::Module {
+        SourceFile::parse(text, Edition::Edition2021)
+            .tree()
+            .syntax()
+            .descendants()
+            .find_map(ast::Module::cast)
+            .unwrap()
+    }
+
+    #[test]
+    fn path_attributes_include_cfg_attr_paths() {
+        assert!(path_attributes(&first_module("mod a;")).is_empty());
+        assert_eq!(
+            path_attributes(&first_module(r#"#[path = "b.rs"] mod a;"#)),
+            ["b.rs"]
+        );
+        assert_eq!(
+            path_attributes(&first_module(
+                r#"
+                #[cfg_attr(unix, path = "unix.rs")]
+                #[cfg_attr(all(windows, feature = "x"), path = "windows.rs")]
+                #[doc = "a.rs"]
+                mod a;
+                "#
+            )),
+            ["unix.rs", "windows.rs"]
+        );
+        assert_eq!(
+            path_attributes(&first_module(r#"#[path = "dir\\a.rs"] mod a;"#)),
+            ["dir\\a.rs"]
+        );
+    }
+
+    /// The files the `mod foo;` declaration in `text` can refer to, relative to `dir`, when `text`
+    /// is the file at `path` in module `module`
+    fn declared(dir: &TempDir, path: &str, module: &[&str], text: &str) -> Vec<String> {
+        let (mut db, _client) = test_utils::database();
+        let file = FileUrl::from_path(&dir.0.join(path)).unwrap();
+        let module_path = module
+            .iter()
+            .fold(ModulePath::crate_root(), |path, segment| {
+                path.child(segment)
+            });
+        db.files.insert(
+            file.clone(),
+            FileData {
+                module_path,
+                ..FileData::default()
+            },
+        );
+
+        let declaration = SourceFile::parse(text, Edition::Edition2021)
+            .tree()
+            .syntax()
+            .descendants()
+            .filter_map(ast::Module::cast)
+            .find(|module| module.item_list().is_none())
+            .unwrap();
+        declared_files(&db, &file, &declaration)
+            .into_iter()
+            .map(|path| path.strip_prefix(&dir.0).unwrap().display().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn declared_files_are_next_to_mod_rs_like_files() {
+        let dir = TempDir::new(
+            "declared-mod-rs",
+            &[("src/lib.rs", ""), ("src/bar/mod.rs", "")],
+        );
+        assert_eq!(
+            declared(&dir, "src/lib.rs", &[], "mod a;"),
+            ["src/a.rs", "src/a/mod.rs"]
+        );
+        assert_eq!(
+            declared(&dir, "src/bar/mod.rs", &["bar"], "mod a;"),
+            ["src/bar/a.rs", "src/bar/a/mod.rs"]
+        );
+    }
+
+    #[test]
+    fn declared_files_of_other_files_are_in_their_own_directory() {
+        let dir = TempDir::new("declared-file", &[("src/foo.rs", "")]);
+        assert_eq!(
+            declared(&dir, "src/foo.rs", &["foo"], "mod a;"),
+            ["src/foo/a.rs", "src/foo/a/mod.rs"]
+        );
+        assert_eq!(
+            declared(&dir, "src/foo.rs", &["foo"], "mod inner { mod a; }"),
+            ["src/foo/inner/a.rs", "src/foo/inner/a/mod.rs"]
+        );
+    }
+
+    #[test]
+    fn path_attributes_are_relative_to_the_declaring_file() {
+        let dir = TempDir::new("declared-path", &[("src/foo.rs", "")]);
+        assert_eq!(
+            declared(&dir, "src/foo.rs", &["foo"], r#"#[path = "b.rs"] mod a;"#),
+            ["src/b.rs"]
+        );
+        assert_eq!(
+            declared(
+                &dir,
+                "src/foo.rs",
+                &["foo"],
+                r#"mod inner { #[path = "b.rs"] mod a; }"#
+            ),
+            ["src/foo/inner/b.rs"]
+        );
+        assert_eq!(
+            declared(
+                &dir,
+                "src/foo.rs",
+                &["foo"],
+                r#"#[path = "other"] mod inner { mod a; }"#
+            ),
+            ["src/foo/other/a.rs", "src/foo/other/a/mod.rs"]
+        );
+    }
+}
@@ -0,0 +1,53 @@
+use lsp_types::Location;
+
+use crate::database::Database;
+use crate::database::resolve::{
+    Resolution, is_unknown_member_access, module_at, path_at_token, resolve_path,
+};
+use crate::utils::token_at_offset;
+
+/// Every use of `name` in the workspace that could refer to `target`. Uses that resolve to
+/// something else are skipped, but uses that can't be resolved at all are kept, since they might
+/// still be references. With no target, every use of the name is returned.
+pub fn find_references(db: &Database, target: Option<&Resolution>, name: &str) -> Vec<Location> {
+    let is_member = matches!(
+        target,
+        Some(Resolution::Child { .. } | Resolution::Associated { .. })
+    );
+
+    db.name_refs
+        .get(name)
+        .into_iter()
+        .flatten()
+        .filter(|name_ref| {
+            let Some(target) = target else {
+                return true;
+            };
+            let Some(file) = db.files.get(&name_ref.file_path) else {
+                return false;
+            };
+            let Some(token) = file
+                .index
+                .offset(name_ref.range.start)
+                .and_then(|offset| token_at_offset(&file.ast.syntax_node(), offset))
+            else {
+                return false;
+            };
+
+            if is_unknown_member_access(&token) {
+                return is_member;
+            }
+
+            let module = module_at(
+                &file.module_path,
+                &token.parent().expect("token had no parent"),
+            );
+            let segments = path_at_token(&token).unwrap_or_else(|| vec![name.to_string()]);
+            match resolve_path(db, &module, &segments) {
+                Some(resolution) => &resolution == target,
+                None => true,
+            }
+        })
+        .map(|name_ref| Location::new(name_ref.file_path.url().clone(), name_ref.range))
+        .collect()
+}
@@ -0,0 +1,792 @@
+use std::collections::HashMap;
+
+use lsp_types::{Location, Position};
+use rust_analyzer_syntax::ast::{self, HasName, PathSegmentKind};
+use rust_analyzer_syntax::{AstNode, SyntaxKind, SyntaxNode, SyntaxToken};
+use snafu::{OptionExt, Whatever};
+
+use crate::database::doc_links::doc_link_at;
+use crate::database::manifest::dependency_crate;
+use crate::database::{
+    AssociatedItemData, Database, FileData, ImportData, ItemKind, ItemPath, ModulePath, Visibility,
+};
+use crate::utils::token_at_offset;
+
+/// What a path resolved to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Resolution {
+    Module(ModulePath),
+    Item(ItemPath),
+    /// A field or variant of the item
+    Child {
+        owner: ItemPath,
+        name: String,
+    },
+    /// An item from an `impl` or `trait` block belonging to the owner
+    Associated {
+        owner: ItemPath,
+        name: String,
+    },
+}
+
+impl Resolution {
+    /// Every definition the resolution refers to; items and their members can be defined more than
+    /// once when a name is declared twice in a module (usually behind different `#[cfg]`s)
+    pub fn locations(&self, db: &Database) -> Vec<Location> {
+        match self {
+            Resolution::Module(module) => db
+                .modules
+                .get(module)
+                .map(|data| Location::new(data.file_path.url().clone(), data.range))
+                .into_iter()
+                .collect(),
+            Resolution::Item(item) => db
+                .items
+                .get(item)
+                .into_iter()
+                .flatten()
+                .map(|data| Location::new(data.file_path.url().clone(), data.range))
+                .collect(),
+            Resolution::Child { owner, name } => db
+                .items
+                .get(owner)
+                .into_iter()
+                .flatten()
+                .flat_map(|owner| {
+                    owner
+                        .children
+                        .iter()
+                        .filter(|child| &child.name == name)
+                        .map(|child| Location::new(owner.file_path.url().clone(), child.range))
+                })
+                .collect(),
+            Resolution::Associated { owner, name } => find_associated(db, owner, name)
+                .map(|data| Location::new(data.file_path.url().clone(), data.range))
+                .collect(),
+        }
+    }
+
+    /// Like [`Resolution::locations`], but only the name of each definition. Modules are named where
+    /// they're declared with `mod`.
+    pub fn name_locations(&self, db: &Database) -> Vec<Location> {
+        match self {
+            Resolution::Module(module) => db
+                .files
+                .iter()
+                .flat_map(|(file, data)| {
+                    data.modules
+                        .iter()
+                        .filter(|include| &include.module == module)
+                        .map(|include| Location::new(file.url().clone(), include.name_range))
+                })
+                .collect(),
+            Resolution::Item(item) => db
+                .items
+                .get(item)
+                .into_iter()
+                .flatten()
+                .map(|data| Location::new(data.file_path.url().clone(), data.name_range))
+                .collect(),
+            Resolution::Child { owner, name } => db
+                .items
+                .get(owner)
+                .into_iter()
+                .flatten()
+                .flat_map(|owner| {
+                    owner
+                        .children
+                        .iter()
+                        .filter(|child| &child.name == name)
+                        .map(|child| Location::new(owner.file_path.url().clone(), child.name_range))
+                })
+                .collect(),
+            Resolution::Associated { owner, name } => find_associated(db, owner, name)
+                .map(|data| Location::new(data.file_path.url().clone(), data.name_range))
+                .collect(),
+        }
+    }
+}
+
+/// Every item, associated item, field or variant called `name` in the workspace, closest to
+/// `module` first. This is the fallback for paths that can't be resolved properly.
+pub fn find_by_name(db: &Database, module: &ModulePath, name: &str) -> Vec<Location> {
+    let items = db
+        .items
+        .iter()
+        .filter(|(path, _)| path.name == name)
+        .flat_map(|(path, defs)| {
+            defs.iter().map(|data| {
+                let location = Location::new(data.file_path.url().clone(), data.range);
+                (&path.module, location)
+            })
+        });
+
+    let children = db.items.iter().flat_map(|(path, defs)| {
+        defs.iter().flat_map(move |data| {
+            data.children
+                .iter()
+                .filter(move |child| child.name == name)
+                .map(|child| {
+                    let location = Location::new(data.file_path.url().clone(), child.range);
+                    (&path.module, location)
+                })
+        })
+    });
+
+    let associated = db
+        .associated_items
+        .values()
+        .flatten()
+        .filter(|item| item.name == name)
+        .map(|item| {
+            let location = Location::new(item.file_path.url().clone(), item.range);
+            (&item.module, location)
+        });
+
+    let mut candidates = items.chain(children).chain(associated).collect::<Vec<_>>();
+    candidates.sort_by_key(|(candidate, _)| module_distance(module, candidate));
+    candidates
+        .into_iter()
+        .map(|(_, location)| location)
+        .collect()
+}
+
+/// Every impl of the trait or type `segments` resolves to. Paths that don't resolve, such as traits
+/// from crates outside the workspace, are matched against impls by name instead.
+pub fn find_impls(db: &Database, module: &ModulePath, segments: &[String]) -> Vec<Location> {
+    let Some(name) = segments.last() else {
+        return vec![];
+    };
+    let target = match resolve_path(db, module, segments) {
+        Some(Resolution::Item(item)) => Some(Resolution::Item(item)),
+        _ => None,
+    };
+    let is_trait = match &target {
+        Some(Resolution::Item(item)) => db
+            .items
+            .get(item)
+            .is_some_and(|defs| defs.iter().any(|def| def.kind == ItemKind::Trait)),
+        _ => false,
+    };
+
+    let matches = |path: &Option<Vec<String>>, module: &ModulePath| {
+        let Some(path) = path else {
+            return false;
+        };
+        match &target {
+            Some(target) => resolve_path(db, module, path).as_ref() == Some(target),
+            None => path.last() == Some(name),
+        }
+    };
+
+    let mut locations = db
+        .files
+        .iter()
+        .flat_map(|(file, data)| {
+            data.impls
+                .iter()
+                .filter(|impl_| match (&target, is_trait) {
+                    (Some(_), true) => matches(&impl_.trait_, &impl_.module),
+                    (Some(_), false) => matches(&impl_.self_ty, &impl_.module),
+                    (None, _) => {
+                        matches(&impl_.trait_, &impl_.module)
+                            || matches(&impl_.self_ty, &impl_.module)
+                    }
+                })
+                .map(|impl_| Location::new(file.url().clone(), impl_.range))
+        })
+        .collect::<Vec<_>>();
+
+    // All the traits of a `#[derive]` share its range
+    locations.dedup();
+    locations
+}
+
+/// How many steps through the module tree it takes to get from `from` to `to`. Crate roots are
+/// treated as children of a shared root, so modules in other crates are always further away than
+/// anything in the same crate.
+pub fn module_distance(from: &ModulePath, to: &ModulePath) -> usize {
+    if from.crate_ != to.crate_ {
+        return from.segments.len() + to.segments.len() + 2;
+    }
+
+    let common = from
+        .segments
+        .iter()
+        .zip(&to.segments)
+        .take_while(|(from, to)| from == to)
+        .count();
+    from.segments.len() + to.segments.len() - 2 * common
+}
+
+/// Whether something with `visibility`, declared in `module`, can be named from `from`. Private
+/// things are visible in the module they're declared in and all of its descendants.
+pub fn is_visible(visibility: &Visibility, module: &ModulePath, from: &ModulePath) -> bool {
+    let scope = match visibility {
+        Visibility::Public => return true,
+        Visibility::Crate => return module.crate_ == from.crate_,
+        Visibility::Private => Some(module.clone()),
+        Visibility::Super => module.parent(),
+        Visibility::In(path) => {
+            path.split("::")
+                .try_fold(module.clone(), |scope, segment| match segment.trim() {
+                    "crate" => Some(ModulePath {
+                        crate_: scope.crate_,
+                        segments: vec![],
+                    }),
+                    "self" => Some(scope),
+                    "super" => scope.parent(),
+                    name => Some(scope.child(name)),
+                })
+        }
+    };
+
+    scope.is_some_and(|scope| {
+        scope.crate_ == from.crate_ && from.segments.starts_with(&scope.segments)
+    })
+}
+
+/// Resolve `segments` as a path written inside `module`, following `crate::`, `self::`, `super::`,
+/// child modules and imports.
+///
+/// If the first segment isn't found in `module`, it is looked up in the crate root, which covers
+/// 2015-edition paths and is a decent guess otherwise.
+pub fn resolve_path(db: &Database, module: &ModulePath, segments: &[String]) -> Option<Resolution> {
+    resolve_path_inner(db, module, segments, &mut HashMap::new()).or_else(|| {
+        let root = ModulePath {
+            crate_: module.crate_.clone(),
+            segments: vec![],
+        };
+        match segments.first().map(String::as_str) {
+            Some("crate" | "self" | "super") | None => None,
+            Some(_) if &root == module => None,
+            Some(_) => resolve_path_inner(db, &root, segments, &mut HashMap::new()),
+        }
+    })
+}
+
+/// The names looked up in each module while resolving a path, with what they resolved to. Imports
+/// can refer to each other (and to themselves, through globs), and without this modules that glob
+/// import each other would be searched over and over.
+type Visited = HashMap<(ModulePath, String), Option<Resolution>>;
+
+fn resolve_path_inner(
+    db: &Database,
+    module: &ModulePath,
+    segments: &[String],
+    visited: &mut Visited,
+) -> Option<Resolution> {
+    let (first, rest) = segments.split_first()?;
+    let mut current = match first.as_str() {
+        "crate" => Resolution::Module(ModulePath {
+            crate_: module.crate_.clone(),
+            segments: vec![],
+        }),
+        "self" => Resolution::Module(module.clone()),
+        "super" => Resolution::Module(module.parent()?),
+        name => {
+            resolve_member(db, module, name, visited).or_else(|| extern_crate(db, module, name))?
+        }
+    };
+
+    for segment in rest {
+        current = match current {
+            Resolution::Module(module) => match segment.as_str() {
+                "self" => Resolution::Module(module),
+                "super" => Resolution::Module(module.parent()?),
+                name => resolve_member(db, &module, name, visited)?,
+            },
+            Resolution::Item(owner) => resolve_item_member(db, &owner, segment)?,
+            Resolution::Child { .. } | Resolution::Associated { .. } => return None,
+        };
+    }
+
+    Some(current)
+}
+
+/// Look up `name` inside `module`, reusing the result if it was looked up before. A lookup that's
+/// still in progress finds nothing, which breaks cycles of imports.
+fn resolve_member(
+    db: &Database,
+    module: &ModulePath,
+    name: &str,
+    visited: &mut Visited,
+) -> Option<Resolution> {
+    let key = (module.clone(), name.to_string());
+    if let Some(resolution) = visited.get(&key) {
+        return resolution.clone();
+    }
+
+    visited.insert(key.clone(), None);
+    let resolution = lookup_member(db, module, name, visited);
+    visited.insert(key, resolution.clone());
+    resolution
+}
+
+/// Look up `name` inside `module`: its items, its child modules, then its imports
+fn lookup_member(
+    db: &Database,
+    module: &ModulePath,
+    name: &str,
+    visited: &mut Visited,
+) -> Option<Resolution> {
+    let item = ItemPath {
+        module: module.clone(),
+        name: name.to_string(),
+    };
+    if db.items.contains_key(&item) {
+        return Some(Resolution::Item(item));
+    }
+
+    let child = module.child(name);
+    if db.modules.contains_key(&child) {
+        return Some(Resolution::Module(child));
+    }
+
+    let imports = module_imports(db, module);
+
+    let named = imports
+        .clone()
+        .filter(|import| import.name.as_deref() == Some(name))
+        .find_map(|import| resolve_path_inner(db, module, &import.path, visited));
+    if named.is_some() {
+        return named;
+    }
+
+    imports.filter(|import| import.glob).find_map(|import| {
+        match resolve_path_inner(db, module, &import.path, visited)? {
+            Resolution::Module(globbed) => resolve_member(db, &globbed, name, visited),
+            Resolution::Item(owner) => resolve_item_member(db, &owner, name),
+            Resolution::Child { .. } | Resolution::Associated { .. } => None,
+        }
+    })
+}
+
+/// The root of the crate that `module`'s crate depends on as `name`
+fn extern_crate(db: &Database, module: &ModulePath, name: &str) -> Option<Resolution> {
+    let root = ModulePath {
+        crate_: dependency_crate(db, &module.crate_, name)?,
+        segments: vec![],
+    };
+    db.modules
+        .contains_key(&root)
+        .then_some(Resolution::Module(root))
+}
+
+/// Look up `name` among the fields or variants of `owner`, then its associated items
+fn resolve_item_member(db: &Database, owner: &ItemPath, name: &str) -> Option<Resolution> {
+    let (owner, name) = (owner.clone(), name.to_string());
+    let has_child = db
+        .items
+        .get(&owner)?
+        .iter()
+        .flat_map(|data| &data.children)
+        .any(|child| child.name == name);
+
+    if has_child {
+        Some(Resolution::Child { owner, name })
+    } else if find_associated(db, &owner, &name).next().is_some() {
+        Some(Resolution::Associated { owner, name })
+    } else {
+        None
+    }
+}
+
+/// Find the associated items called `name` whose `impl` or `trait` block resolves to `owner`
+pub fn find_associated<'db>(
+    db: &'db Database,
+    owner: &'db ItemPath,
+    name: &'db str,
+) -> impl Iterator<Item = &'db AssociatedItemData> {
+    db.associated_items
+        .get(&owner.name)
+        .into_iter()
+        .flatten()
+        .filter(move |item| item.name == name)
+        .filter(|item| {
+            resolve_path(db, &item.module, &item.owner) == Some(Resolution::Item(owner.clone()))
+        })
+}
+
+/// The imports written directly inside `module`
+pub fn module_imports<'db>(
+    db: &'db Database,
+    module: &'db ModulePath,
+) -> impl Iterator<Item = &'db ImportData> + Clone {
+    db.modules
+        .get(module)
+        .and_then(|data| db.files.get(&data.file_path))
+        .into_iter()
+        .flat_map(|file| file.imports.iter())
+        .filter(move |import| &import.module == module)
+}
+
+/// The path named at a position in a file, along with where it was written
+pub struct PathAtPosition {
+    pub token: SyntaxToken,
+    /// The module the path is written in
+    pub module: ModulePath,
+    pub segments: Vec<String>,
+}
+
+/// Find the path under the cursor. Tokens that aren't part of a path are treated as a bare name.
+pub fn path_at_position(file: &FileData, position: Position) -> Result<PathAtPosition, Whatever> {
+    let offset = file
+        .index
+        .offset(position)
+        .whatever_context("position was outside of the file")?;
+    let token = token_at_offset(&file.ast.syntax_node(), offset)
+        .whatever_context("no token at position")?;
+
+    let module = module_at(
+        &file.module_path,
+        &token.parent().expect("token had no parent"),
+    );
+    let doc_link = match token.kind() {
+        SyntaxKind::COMMENT => doc_link_at(&token, offset),
+        _ => None,
+    };
+    let segments = doc_link
+        .or_else(|| path_at_token(&token))
+        .unwrap_or_else(|| vec![token.text().to_string()]);
+
+    Ok(PathAtPosition {
+        token,
+        module,
+        segments,
+    })
+}
+
+/// Resolve whatever is under the cursor, whether it's a path or the name of a declaration
+pub fn resolve_at(db: &Database, path: &PathAtPosition) -> Option<Resolution> {
+    resolve_declaration(db, &path.module, &path.token)
+        .or_else(|| resolve_path(db, &path.module, &path.segments))
+}
+
+/// What a declared name (like the `Foo` in `struct Foo`) refers to, given the module it's in
+pub fn resolve_declaration(
+    db: &Database,
+    module: &ModulePath,
+    token: &SyntaxToken,
+) -> Option<Resolution> {
+    let name = token.parent().and_then(ast::Name::cast)?;
+    let text = name.text_non_mutable().to_string();
+    let declaration = name.syntax().parent()?;
+    let in_assoc_item_list =
+        declaration.parent().map(|parent| parent.kind()) == Some(SyntaxKind::ASSOC_ITEM_LIST);
+
+    match declaration.kind() {
+        SyntaxKind::RECORD_FIELD | SyntaxKind::VARIANT => {
+            let owner = declaration.ancestors().find_map(ast::Adt::cast)?;
+            let owner = ItemPath {
+                module: module.clone(),
+                name: owner.name()?.text_non_mutable().to_string(),
+            };
+            Some(Resolution::Child { owner, name: text })
+        }
+        // `module` already includes inline modules, since their names are inside them
+        SyntaxKind::MODULE
+            if ast::Module::cast(declaration.clone())?
+                .item_list()
+                .is_some() =>
+        {
+            Some(Resolution::Module(module.clone()))
+        }
+        SyntaxKind::MODULE => Some(Resolution::Module(module.child(&text))),
+        kind if in_assoc_item_list && ItemKind::from_syntax_kind(kind).is_some() => {
+            let owner = declaration.ancestors().find_map(associated_owner)?;
+            let Resolution::Item(owner) = resolve_path(db, module, &owner)? else {
+                return None;
+            };
+            Some(Resolution::Associated { owner, name: text })
+        }
+        kind if ItemKind::from_syntax_kind(kind).is_some() || kind == SyntaxKind::RENAME => {
+            Some(Resolution::Item(ItemPath {
+                module: module.clone(),
+                name: text,
+            }))
+        }
+        _ => None,
+    }
+}
+
+/// Whether `token` is a field or method accessed on something other than `self`, which can't be
+/// resolved without type information
+pub fn is_unknown_member_access(token: &SyntaxToken) -> bool {
+    let is_member = token
+        .parent()
+        .and_then(|name_ref| name_ref.parent())
+        .is_some_and(|parent| {
+            matches!(
+                parent.kind(),
+                SyntaxKind::FIELD_EXPR | SyntaxKind::METHOD_CALL_EXPR
+            )
+        });
+    is_member && path_at_token(token).is_none()
+}
+
+/// The module containing `node`, given the module path of the file it belongs to
+pub fn module_at(file_module: &ModulePath, node: &SyntaxNode) -> ModulePath {
+    let mut inline_modules = node
+        .ancestors()
+        .filter_map(ast::Module::cast)
+        .filter(|module| module.item_list().is_some())
+        .filter_map(|module| Some(module.name()?.text_non_mutable().to_string()))
+        .collect::<Vec<_>>();
+    inline_modules.reverse();
+
+    let mut module = file_module.clone();
+    module.segments.extend(inline_modules);
+    module
+}
+
+/// The textual segments of `path`, e.g. `["crate", "net", "Client"]`. Returns `None` for paths
+/// containing segments that can't be resolved by name, such as `<T as Trait>`.
+pub fn path_segments(path: &ast::Path) -> Option<Vec<String>> {
+    let mut segments = vec![];
+    let mut current = Some(path.clone());
+    while let Some(path) = current {
+        let segment = match path.segment()?.kind()? {
+            PathSegmentKind::Name(name_ref) => name_ref.text_non_mutable().to_string(),
+            PathSegmentKind::SelfTypeKw => "Self".to_string(),
+            PathSegmentKind::SelfKw => "self".to_string(),
+            PathSegmentKind::SuperKw => "super".to_string(),
+            PathSegmentKind::CrateKw => "crate".to_string(),
+            PathSegmentKind::Type { .. } => return None,
+        };
+        segments.push(segment);
+        current = path.qualifier();
+    }
+    segments.reverse();
+    Some(segments)
+}
+
+/// The path prefix contributed by the use trees enclosing `tree`, e.g. `["foo"]` for the `Bar` in
+/// `use foo::{Bar, Baz};`
+pub fn use_tree_prefix(tree: &ast::UseTree) -> Option<Vec<String>> {
+    let Some(parent) = tree
+        .syntax()
+        .parent()
+        .and_then(ast::UseTreeList::cast)
+        .and_then(|list| list.syntax().parent())
+        .and_then(ast::UseTree::cast)
+    else {
+        return Some(vec![]);
+    };
+
+    let mut prefix = use_tree_prefix(&parent)?;
+    if let Some(path) = parent.path() {
+        prefix.extend(path_segments(&path)?);
+    }
+    Some(prefix)
+}
+
+/// The full path written up to and including the path segment `token` is part of. Paths inside
+/// `use` trees include the prefix of their enclosing trees, and a leading `Self` is replaced by the
+/// enclosing impl's self type.
+///
+/// Members written outside of paths are given the path of their owner, so `self.field`,
+/// `self.method()` and `Foo { field: 0 }` become `Self::field`, `Self::method` and `Foo::field`.
+pub fn path_at_token(token: &SyntaxToken) -> Option<Vec<String>> {
+    if let Some(name_ref) = token.parent().and_then(ast::NameRef::cast)
+        && let Some(path) = member_path(&name_ref)
+    {
+        return Some(path);
+    }
+
+    let segment = token.parent_ancestors().find_map(ast::PathSegment::cast)?;
+    let path = segment.parent_path();
+
+    let outermost = path
+        .syntax()
+        .ancestors()
+        .take_while(|node| node.kind() == SyntaxKind::PATH)
+        .last()?;
+    let mut segments = match outermost.parent().and_then(ast::UseTree::cast) {
+        Some(tree) => use_tree_prefix(&tree)?,
+        None => vec![],
+    };
+
+    segments.extend(path_segments(&path)?);
+    replace_self_type(path.syntax(), segments)
+}
+
+fn member_path(name_ref: &ast::NameRef) -> Option<Vec<String>> {
+    let parent = name_ref.syntax().parent()?;
+    let receiver = ast::FieldExpr::cast(parent.clone())
+        .and_then(|field| field.expr())
+        .or_else(|| ast::MethodCallExpr::cast(parent.clone()).and_then(|call| call.receiver()));
+
+    let mut owner = if let Some(receiver) = receiver {
+        if receiver.syntax().text() != "self" {
+            return None;
+        }
+        vec!["Self".to_string()]
+    } else if let Some(field) = ast::RecordExprField::cast(parent.clone()) {
+        let record = field.syntax().ancestors().find_map(ast::RecordExpr::cast)?;
+        path_segments(&record.path()?)?
+    } else if let Some(field) = ast::RecordPatField::cast(parent) {
+        let record = field.syntax().ancestors().find_map(ast::RecordPat::cast)?;
+        path_segments(&record.path()?)?
+    } else {
+        return None;
+    };
+
+    owner.push(name_ref.text_non_mutable().to_string());
+    replace_self_type(name_ref.syntax(), owner)
+}
+
+/// Replace a leading `Self` with the path of the enclosing impl's self type (or trait)
+pub fn replace_self_type(node: &SyntaxNode, segments: Vec<String>) -> Option<Vec<String>> {
+    if segments.first().is_none_or(|segment| segment != "Self") {
+        return Some(segments);
+    }
+
+    let mut self_type = node.ancestors().find_map(associated_owner)?;
+    self_type.extend(segments.into_iter().skip(1));
+    Some(self_type)
+}
+
+/// The path of the type an `impl` block is for, or the name of a trait. Returns `None` for other
+/// nodes, and for impls on types that aren't plain paths (like `impl Foo for &Bar`).
+pub fn associated_owner(node: SyntaxNode) -> Option<Vec<String>> {
+    if let Some(trait_) = ast::Trait::cast(node.clone()) {
+        return Some(vec![trait_.name()?.text_non_mutable().to_string()]);
+    }
+
+    let impl_ = ast::Impl::cast(node)?;
+    match impl_.self_ty()? {
+        ast::Type::PathType(path_type) => path_segments(&path_type.path()?),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::FileUrl;
+    use crate::test_utils::{self, TempDir};
+
+    fn module(crate_: &str, segments: &[&str]) -> ModulePath {
+        ModulePath {
+            crate_: crate_.to_string(),
+            segments: segments.iter().map(|segment| segment.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn distance_counts_steps_through_the_common_ancestor() {
+        let a_b = module("crate", &["a", "b"]);
+        assert_eq!(module_distance(&a_b, &a_b), 0);
+        assert_eq!(module_distance(&a_b, &module("crate", &["a"])), 1);
+        assert_eq!(module_distance(&a_b, &module("crate", &["a", "c", "d"])), 3);
+        assert_eq!(module_distance(&a_b, &module("crate", &[])), 2);
+        assert_eq!(module_distance(&a_b, &module("other", &["x"])), 5);
+    }
+
+    #[test]
+    fn private_items_are_visible_in_their_module_and_its_descendants() {
+        let a = module("crate", &["a"]);
+        assert!(is_visible(&Visibility::Private, &a, &a));
+        assert!(is_visible(
+            &Visibility::Private,
+            &a,
+            &module("crate", &["a", "b"])
+        ));
+        assert!(!is_visible(&Visibility::Private, &a, &module("crate", &[])));
+        assert!(!is_visible(
+            &Visibility::Private,
+            &a,
+            &module("crate", &["ab"])
+        ));
+        assert!(!is_visible(
+            &Visibility::Private,
+            &a,
+            &module("other", &["a"])
+        ));
+    }
+
+    #[test]
+    fn restricted_visibility_widens_the_scope() {
+        let a_b = module("crate", &["a", "b"]);
+        let a_c = module("crate", &["a", "c"]);
+        assert!(is_visible(&Visibility::Public, &a_b, &module("other", &[])));
+        assert!(is_visible(&Visibility::Crate, &a_b, &module("crate", &[])));
+        assert!(!is_visible(&Visibility::Crate, &a_b, &module("other", &[])));
+        assert!(is_visible(&Visibility::Super, &a_b, &a_c));
+        assert!(!is_visible(
+            &Visibility::Super,
+            &a_b,
+            &module("crate", &["d"])
+        ));
+        assert!(!is_visible(&Visibility::Super, &module("crate", &[]), &a_c));
+
+        let in_path = |path: &str| Visibility::In(path.to_string());
+        assert!(is_visible(&in_path("crate::a"), &a_b, &a_c));
+        assert!(!is_visible(
+            &in_path("crate::a"),
+            &a_b,
+            &module("crate", &[])
+        ));
+        assert!(is_visible(&in_path("super"), &a_b, &a_c));
+        assert!(is_visible(&in_path("self"), &a_b, &a_b));
+        assert!(!is_visible(&in_path("self"), &a_b, &a_c));
+    }
+
+    #[test]
+    fn modules_that_glob_import_each_other_resolve_quickly() {
+        // Every module glob imports every other one, so a name that doesn't exist would be looked
+        // for along every chain of imports without remembering what was looked up already
+        let names = ["a", "b", "c", "d", "e", "f"];
+        let globs = names
+            .iter()
+            .map(|name| format!("pub use crate::{name}::*;\n"))
+            .collect::<String>();
+        let lib = names
+            .iter()
+            .map(|name| format!("mod {name};\n"))
+            .chain([globs.clone()])
+            .collect::<String>();
+        let modules = names
+            .iter()
+            .map(|name| {
+                let text = format!(
+                    "use super::*;\n{globs}pub struct {}Item;\n",
+                    name.to_uppercase()
+                );
+                (format!("{name}.rs"), text)
+            })
+            .collect::<Vec<_>>();
+        let files = std::iter::once(("lib.rs", lib.as_str()))
+            .chain(
+                modules
+                    .iter()
+                    .map(|(path, text)| (path.as_str(), text.as_str())),
+            )
+            .collect::<Vec<_>>();
+        let dir = TempDir::new("glob-cycle", &files);
+
+        let (mut db, _client) = test_utils::database();
+        db.load_file(&FileUrl::from_path(&dir.0.join("lib.rs")).unwrap())
+            .unwrap();
+
+        let root = ModulePath::crate_root();
+        let path = |path: &str| path.split("::").map(String::from).collect::<Vec<_>>();
+        assert_eq!(
+            resolve_path(&db, &root, &path("FItem")),
+            Some(Resolution::Item(ItemPath {
+                module: root.child("f"),
+                name: "FItem".to_string(),
+            }))
+        );
+        assert_eq!(
+            resolve_path(&db, &root.child("a"), &path("BItem")),
+            Some(Resolution::Item(ItemPath {
+                module: root.child("b"),
+                name: "BItem".to_string(),
+            }))
+        );
+        assert_eq!(resolve_path(&db, &root.child("a"), &path("Missing")), None);
+        assert_eq!(resolve_path(&db, &root, &path("c::Missing")), None);
+    }
+}
@@ -0,0 +1,464 @@
+use rust_analyzer_syntax::ast::{self, HasLoopBody, HasName};
+use rust_analyzer_syntax::{AstNode, SyntaxKind, SyntaxNode, SyntaxToken, TextSize};
+
+/// The token declaring the local binding, generic parameter, lifetime or label that `token`
+/// refers to, found by walking outwards through the enclosing scopes. Returns `None` for anything
+/// that isn't scoped to an item, so it can be resolved as a path instead.
+pub fn find_local(token: &SyntaxToken) -> Option<SyntaxToken> {
+    match token.kind() {
+        SyntaxKind::IDENT => find_ident(token),
+        SyntaxKind::LIFETIME_IDENT => find_lifetime(token),
+        _ => None,
+    }
+}
+
+/// The name of the item declared in a block around `token` that it refers to, if it's the start of
+/// a path. Such items can only be named from inside the block, so they aren't part of the module
+/// they're in.
+pub fn find_block_item(token: &SyntaxToken) -> Option<SyntaxToken> {
+    let parent = token.parent()?;
+    if token.kind() != SyntaxKind::IDENT || !is_first_segment(&parent) {
+        return None;
+    }
+
+    parent
+        .ancestors()
+        .filter_map(ast::StmtList::cast)
+        .flat_map(|list| list.statements())
+        .find_map(|stmt| match stmt {
+            ast::Stmt::Item(item) => {
+                let name = ast::AnyHasName::cast(item.syntax().clone())?
+                    .name()?
+                    .ident_token()?;
+                (name.text() == token.text()).then_some(name)
+            }
+            _ => None,
+        })
+}
+
+/// Every token in `root` that refers to `binding`, including `binding` itself
+pub fn find_local_uses(root: &SyntaxNode, binding: &SyntaxToken) -> Vec<SyntaxToken> {
+    root.descendants_with_tokens()
+        .filter_map(|element| element.into_token())
+        .filter(|token| token.kind() == binding.kind() && token.text() == binding.text())
+        .filter(|token| {
+            find_local(token).is_some_and(|found| found.text_range() == binding.text_range())
+        })
+        .collect()
+}
+
+fn find_ident(token: &SyntaxToken) -> Option<SyntaxToken> {
+    let parent = token.parent()?;
+    // A binding is its own definition
+    if let Some(name) = ast::Name::cast(parent.clone()) {
+        let is_binding = name
+            .syntax()
+            .parent()
+            .is_some_and(|parent| match parent.kind() {
+                SyntaxKind::IDENT_PAT => {
+                    ast::IdentPat::cast(parent).is_some_and(|ident| is_binding(&ident))
+                }
+                SyntaxKind::TYPE_PARAM | SyntaxKind::CONST_PARAM => true,
+                _ => false,
+            });
+        return is_binding.then(|| token.clone());
+    }
+
+    let name = token.text();
+    if is_local_use(&parent)
+        && let Some(binding) = local_binding(&parent, name, token.text_range().start())
+    {
+        return Some(binding);
+    }
+
+    is_first_segment(&parent)
+        .then(|| generic_binding(&parent, name))
+        .flatten()
+}
+
+/// Every local binding and generic type or const parameter that could be named at `offset` inside
+/// `node`, for completion
+pub fn visible_locals(node: &SyntaxNode, offset: TextSize) -> Vec<SyntaxToken> {
+    let mut names = vec![];
+    if let Some(item) = node.ancestors().find(|node| is_local_boundary(node.kind())) {
+        names.extend(
+            item.descendants()
+                .filter_map(ast::IdentPat::cast)
+                .filter(is_binding)
+                .filter_map(|ident| Some(ident.name()?.text_non_mutable().to_string())),
+        );
+    }
+    names.extend(
+        node.ancestors()
+            .flat_map(|node| node.children())
+            .filter_map(ast::GenericParamList::cast)
+            .flat_map(|list| list.generic_params())
+            .filter_map(|param| match param {
+                ast::GenericParam::TypeParam(param) => param.name(),
+                ast::GenericParam::ConstParam(param) => param.name(),
+                ast::GenericParam::LifetimeParam(_) => None,
+            })
+            .map(|name| name.text_non_mutable().to_string()),
+    );
+    names.sort();
+    names.dedup();
+
+    // Checking each name the same way a use of it would be resolved keeps scoping consistent
+    names
+        .iter()
+        .filter_map(|name| {
+            local_binding(node, name, offset).or_else(|| generic_binding(node, name))
+        })
+        .collect()
+}
+
+/// The local binding named `name` that code at `offset` inside `node` would see
+fn local_binding(node: &SyntaxNode, name: &str, offset: TextSize) -> Option<SyntaxToken> {
+    for node in node.ancestors() {
+        if let Some(binding) = scope_binding(&node, name, offset) {
+            return Some(binding);
+        }
+
+        // Locals never cross item boundaries
+        if is_local_boundary(node.kind()) {
+            break;
+        }
+    }
+
+    None
+}
+
+fn is_local_boundary(kind: SyntaxKind) -> bool {
+    matches!(
+        kind,
+        SyntaxKind::FN | SyntaxKind::CONST | SyntaxKind::STATIC
+    )
+}
+
+fn find_lifetime(token: &SyntaxToken) -> Option<SyntaxToken> {
+    if matches!(token.text(), "'static" | "'_") {
+        return None;
+    }
+
+    let lifetime = token.parent().and_then(ast::Lifetime::cast)?;
+    let parent = lifetime.syntax().parent()?;
+    match parent.kind() {
+        SyntaxKind::LIFETIME_PARAM | SyntaxKind::LABEL => Some(token.clone()),
+        SyntaxKind::BREAK_EXPR | SyntaxKind::CONTINUE_EXPR => label_binding(&parent, token.text()),
+        _ => generic_binding(&parent, token.text()),
+    }
+}
+
+/// The label named `name` on a loop or block around `node`. Labels aren't visible inside closures
+/// or nested items.
+fn label_binding(node: &SyntaxNode, name: &str) -> Option<SyntaxToken> {
+    for node in node.ancestors() {
+        let label = node
+            .children()
+            .find_map(ast::Label::cast)
+            .and_then(|label| label.lifetime()?.lifetime_ident_token());
+        if let Some(label) = label
+            && label.text() == name
+        {
+            return Some(label);
+        }
+
+        if matches!(
+            node.kind(),
+            SyntaxKind::FN | SyntaxKind::CLOSURE_EXPR | SyntaxKind::CONST | SyntaxKind::STATIC
+        ) {
+            break;
+        }
+    }
+
+    None
+}
+
+/// The generic parameter named `name` (a type, const or lifetime) declared by an item or `for<..>`
+/// binder around `node`. Parameters of an impl or trait are visible in its associated items, but
+/// nothing crosses into other items.
+fn generic_binding(node: &SyntaxNode, name: &str) -> Option<SyntaxToken> {
+    for node in node.ancestors() {
+        let binders = node
+            .children()
+            .filter_map(ast::ForBinder::cast)
+            .filter_map(|binder| binder.generic_param_list());
+        let found = node
+            .children()
+            .filter_map(ast::GenericParamList::cast)
+            .chain(binders)
+            .flat_map(|list| list.generic_params())
+            .filter_map(|param| match param {
+                ast::GenericParam::TypeParam(param) => param.name()?.ident_token(),
+                ast::GenericParam::ConstParam(param) => param.name()?.ident_token(),
+                ast::GenericParam::LifetimeParam(param) => param.lifetime()?.lifetime_ident_token(),
+            })
+            .find(|param| param.text() == name);
+        if found.is_some() {
+            return found;
+        }
+
+        let in_assoc_item_list =
+            node.parent().map(|parent| parent.kind()) == Some(SyntaxKind::ASSOC_ITEM_LIST);
+        if ast::Item::can_cast(node.kind()) && !in_assoc_item_list {
+            break;
+        }
+    }
+
+    None
+}
+
+/// Whether `node` (the parent of an identifier) is the first segment of a path, where a generic
+/// parameter could be named
+fn is_first_segment(node: &SyntaxNode) -> bool {
+    node.kind() == SyntaxKind::NAME_REF
+        && node
+            .parent()
+            .and_then(|segment| segment.parent())
+            .and_then(ast::Path::cast)
+            .is_some_and(|path| path.qualifier().is_none())
+}
+
+/// Whether `node` (the parent of an identifier) is somewhere a local could be used: a
+/// single-segment path expression, a shorthand struct field, or a macro argument
+fn is_local_use(node: &SyntaxNode) -> bool {
+    match node.kind() {
+        SyntaxKind::TOKEN_TREE => true,
+        SyntaxKind::NAME_REF => {
+            let Some(parent) = node.parent() else {
+                return false;
+            };
+
+            if let Some(field) = ast::RecordExprField::cast(parent.clone()) {
+                return field.expr().is_none();
+            }
+
+            let Some(path) = parent.parent().and_then(ast::Path::cast) else {
+                return false;
+            };
+            path.qualifier().is_none()
+                && path
+                    .syntax()
+                    .parent()
+                    .is_some_and(|parent| parent.kind() == SyntaxKind::PATH_EXPR)
+        }
+        _ => false,
+    }
+}
+
+/// The binding named `name` that `node` introduces for code at `offset`, if any
+fn scope_binding(node: &SyntaxNode, name: &str, offset: TextSize) -> Option<SyntaxToken> {
+    let contains = |node: &SyntaxNode| node.text_range().contains_inclusive(offset);
+
+    match node.kind() {
+        // Later `let`s shadow earlier ones, and a `let` isn't in scope in its own initializer (nor
+        // is one still being written, which has no `;` yet)
+        SyntaxKind::STMT_LIST => ast::StmtList::cast(node.clone())?
+            .statements()
+            .filter(|stmt| stmt.syntax().text_range().end() <= offset)
+            .filter_map(|stmt| match stmt {
+                ast::Stmt::LetStmt(let_stmt) if let_stmt.semicolon_token().is_some() => {
+                    let_stmt.pat()
+                }
+                _ => None,
+            })
+            .filter_map(|pat| pat_binding(&pat, name))
+            .last(),
+        SyntaxKind::FN => {
+            let function = ast::Fn::cast(node.clone())?;
+            if !contains(function.body()?.syntax()) {
+                return None;
+            }
+            params_binding(function.param_list()?, name)
+        }
+        SyntaxKind::CLOSURE_EXPR => {
+            let closure = ast::ClosureExpr::cast(node.clone())?;
+            if !contains(closure.body()?.syntax()) {
+                return None;
+            }
+            params_binding(closure.param_list()?, name)
+        }
+        SyntaxKind::MATCH_ARM => {
+            let pat = ast::MatchArm::cast(node.clone())?.pat()?;
+            if contains(pat.syntax()) {
+                return None;
+            }
+            pat_binding(&pat, name)
+        }
+        SyntaxKind::FOR_EXPR => {
+            let for_expr = ast::ForExpr::cast(node.clone())?;
+            if !contains(for_expr.loop_body()?.syntax()) {
+                return None;
+            }
+            pat_binding(&for_expr.pat()?, name)
+        }
+        SyntaxKind::IF_EXPR => {
+            let if_expr = ast::IfExpr::cast(node.clone())?;
+            if if_expr
+                .else_branch()
+                .is_some_and(|branch| contains(branch.syntax()))
+            {
+                return None;
+            }
+            condition_binding(&if_expr.condition()?, name, offset)
+        }
+        SyntaxKind::WHILE_EXPR => {
+            let condition = ast::WhileExpr::cast(node.clone())?.condition()?;
+            condition_binding(&condition, name, offset)
+        }
+        _ => None,
+    }
+}
+
+/// A binding from the `let`s of an `if`/`while` condition (including let chains) that come before
+/// `offset`
+fn condition_binding(condition: &ast::Expr, name: &str, offset: TextSize) -> Option<SyntaxToken> {
+    condition
+        .syntax()
+        .descendants()
+        .filter_map(ast::LetExpr::cast)
+        .filter(|let_expr| let_expr.syntax().text_range().end() <= offset)
+        .filter_map(|let_expr| let_expr.pat())
+        .filter_map(|pat| pat_binding(&pat, name))
+        .last()
+}
+
+fn params_binding(params: ast::ParamList, name: &str) -> Option<SyntaxToken> {
+    params
+        .params()
+        .filter_map(|param| param.pat())
+        .find_map(|pat| pat_binding(&pat, name))
+}
+
+/// The identifier for `name` bound anywhere inside `pat`
+fn pat_binding(pat: &ast::Pat, name: &str) -> Option<SyntaxToken> {
+    pat.syntax()
+        .descendants()
+        .filter_map(ast::IdentPat::cast)
+        .filter(is_binding)
+        .filter_map(|ident| ident.name()?.ident_token())
+        .find(|ident| ident.text() == name)
+}
+
+/// Whether the identifier pattern introduces a binding. A lone capitalized identifier, like `None`
+/// or `MAX`, is taken to name a unit variant, unit struct or constant instead, as it would by
+/// convention.
+pub fn is_binding(ident: &ast::IdentPat) -> bool {
+    ident.ref_token().is_some()
+        || ident.mut_token().is_some()
+        || ident.pat().is_some()
+        || ident
+            .name()
+            .is_none_or(|name| !name.text_non_mutable().starts_with(char::is_uppercase))
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_analyzer_syntax::{Edition, SourceFile};
+
+    use super::*;
+
+    /// Which occurrence of `name` in `text` the `nth` one refers to, if it's scoped to an item
+    fn binding(text: &str, name: &str, nth: usize) -> Option<usize> {
+        let root = SourceFile::parse(text, Edition::Edition2021).syntax_node();
+        let offsets = text
+            .match_indices(name)
+            .map(|(offset, _)| offset)
+            .collect::<Vec<_>>();
+        let token = root
+            .token_at_offset(TextSize::new(offsets[nth] as u32))
+            .right_biased()
+            .unwrap();
+        assert_eq!(token.text(), name);
+
+        let found = find_local(&token)?;
+        offsets
+            .iter()
+            .position(|&offset| offset == usize::from(found.text_range().start()))
+    }
+
+    #[test]
+    fn later_lets_shadow_earlier_bindings() {
+        let text = "fn f(x: u8) { let x = x; let y = x; }";
+        assert_eq!(binding(text, "x", 1), Some(1));
+        assert_eq!(binding(text, "x", 2), Some(0));
+        assert_eq!(binding(text, "x", 3), Some(1));
+    }
+
+    #[test]
+    fn bindings_stay_inside_their_scope() {
+        let text = "fn f(arg: u8) { let g = |arg| arg; arg; { let inner = 1; } inner; }";
+        assert_eq!(binding(text, "arg", 2), Some(1));
+        assert_eq!(binding(text, "arg", 3), Some(0));
+        assert_eq!(binding(text, "inner", 1), None);
+
+        let text = "fn outer() { let value = 1; fn inner() { value; } }";
+        assert_eq!(binding(text, "value", 1), None);
+    }
+
+    #[test]
+    fn condition_bindings_are_only_in_the_then_branch() {
+        let text = "fn f(opt: Option<u8>) { if let Some(value) = opt && value > 1 { value; } else { value; } }";
+        assert_eq!(binding(text, "value", 1), Some(0));
+        assert_eq!(binding(text, "value", 2), Some(0));
+        assert_eq!(binding(text, "value", 3), None);
+    }
+
+    #[test]
+    fn capitalized_patterns_name_items_rather_than_bind() {
+        let text =
+            "fn f(opt: Option<u8>) { match opt { None => None, Some(value) => Some(value) }; }";
+        assert_eq!(binding(text, "None", 0), None);
+        assert_eq!(binding(text, "None", 1), None);
+        assert_eq!(binding(text, "value", 1), Some(0));
+
+        let text = "fn f(opt: u8) { match opt { MAX => MAX, ref Big => Big, Other @ 1 => Other } }";
+        assert_eq!(binding(text, "MAX", 1), None);
+        assert_eq!(binding(text, "Big", 1), Some(0));
+        assert_eq!(binding(text, "Other", 1), Some(0));
+    }
+
+    #[test]
+    fn generic_parameters_are_visible_in_their_item() {
+        let text = "fn f<T>(t: T) -> T { let _: T; }";
+        assert_eq!(binding(text, "T", 1), Some(0));
+        assert_eq!(binding(text, "T", 3), Some(0));
+
+        let text = "fn outer<T>() { fn inner(x: T) {} }";
+        assert_eq!(binding(text, "T", 1), None);
+    }
+
+    #[test]
+    fn impl_and_trait_parameters_are_visible_in_associated_items() {
+        let text = "struct S<T>(T); impl<T> S<T> { fn get(&self) -> T {} }";
+        assert_eq!(binding(text, "T", 1), Some(0));
+        assert_eq!(binding(text, "T", 3), Some(2));
+        assert_eq!(binding(text, "T", 4), Some(2));
+
+        let text = "trait Tr<Item> { fn f(&self, item: Item); } fn g(x: Item) {}";
+        assert_eq!(binding(text, "Item", 1), Some(0));
+        assert_eq!(binding(text, "Item", 2), None);
+    }
+
+    #[test]
+    fn lifetimes_come_from_generics_and_for_binders() {
+        let text = "fn f<'a>(x: &'a u8) -> &'static u8 {}";
+        assert_eq!(binding(text, "'a", 1), Some(0));
+        assert_eq!(binding(text, "'static", 0), None);
+
+        let text = "fn f<F>(g: F) where F: for<'b> Fn(&'b u8) -> &'b u8 {}";
+        assert_eq!(binding(text, "'b", 0), Some(0));
+        assert_eq!(binding(text, "'b", 1), Some(0));
+        assert_eq!(binding(text, "'b", 2), Some(0));
+    }
+
+    #[test]
+    fn labels_resolve_to_the_enclosing_block_or_loop() {
+        let text = "fn f() { 'done: { loop { break 'done; } } 'done: loop { break 'done; } }";
+        assert_eq!(binding(text, "'done", 1), Some(0));
+        assert_eq!(binding(text, "'done", 3), Some(2));
+
+        let text = "fn f() { 'outer: loop { let g = || { break 'outer; }; } }";
+        assert_eq!(binding(text, "'outer", 1), None);
+    }
+}
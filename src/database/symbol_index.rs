@@ -0,0 +1,501 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+use lsp_types::{Location, SymbolKind};
+
+/// An entry's position in the index; ids of removed symbols are reused
+pub type SymbolId = u32;
+
+/// How many results a query returns at most, since clients only show the best few anyway
+const MAX_RESULTS: usize = 256;
+
+#[derive(Debug)]
+pub struct SymbolData {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// The path of the module (and owner, for associated items) the symbol is in, e.g.
+    /// `["crate", "net", "Client"]` for `Client::new`
+    pub container: Vec<String>,
+    pub location: Location,
+}
+
+/// A symbol in a posting list, along with the generation of its id when it was added. Removing a
+/// symbol only bumps its id's generation, leaving its postings to be skipped until the lists are
+/// compacted.
+#[derive(Clone, Copy, Debug)]
+struct Posting {
+    id: SymbolId,
+    generation: u32,
+}
+
+/// Every item in the workspace, searchable by fuzzy name. The index maps pairs of lowercased
+/// characters to the symbols whose names contain them next to each other, with or without the
+/// underscores in between, or as two of the name's word starts in order. A query's candidates are
+/// then the shortest posting list of its pairs of consecutive characters, so `cli` and `nc` find
+/// `NetClient` and `readto` finds `read_to_string`, and the candidates that have every pair are
+/// scored as subsequence matches. Single characters get their own postings, for one character
+/// queries and for finding matches with their characters spread out when there aren't enough
+/// others.
+#[derive(Debug, Default)]
+pub struct SymbolIndex {
+    symbols: Vec<Option<SymbolData>>,
+    generations: Vec<u32>,
+    free: Vec<SymbolId>,
+    chars: HashMap<char, Vec<Posting>>,
+    pairs: HashMap<(char, char), Vec<Posting>>,
+    postings: usize,
+    stale_postings: usize,
+}
+
+impl SymbolIndex {
+    pub fn insert(&mut self, symbol: SymbolData) -> SymbolId {
+        let id = match self.free.pop() {
+            Some(id) => id,
+            None => {
+                self.symbols.push(None);
+                self.generations.push(0);
+                (self.symbols.len() - 1) as SymbolId
+            }
+        };
+        let posting = Posting {
+            id,
+            generation: self.generations[id as usize],
+        };
+
+        let (chars, pairs) = grams(&symbol.name);
+        self.postings += chars.len() + pairs.len();
+        for c in chars {
+            self.chars.entry(c).or_default().push(posting);
+        }
+        for pair in pairs {
+            self.pairs.entry(pair).or_default().push(posting);
+        }
+        self.symbols[id as usize] = Some(symbol);
+        id
+    }
+
+    /// Where the symbol with `id` is, for moving it along with edits around it. Only the location
+    /// can be changed in place, since the postings depend on the name.
+    pub fn location_mut(&mut self, id: SymbolId) -> Option<&mut Location> {
+        let symbol = self.symbols.get_mut(id as usize)?.as_mut()?;
+        Some(&mut symbol.location)
+    }
+
+    pub fn remove(&mut self, id: SymbolId) {
+        let Some(symbol) = self.symbols.get_mut(id as usize).and_then(Option::take) else {
+            return;
+        };
+
+        let (chars, pairs) = grams(&symbol.name);
+        self.stale_postings += chars.len() + pairs.len();
+        self.generations[id as usize] += 1;
+        self.free.push(id);
+
+        if self.stale_postings * 2 > self.postings {
+            self.compact();
+        }
+    }
+
+    /// Drop the postings of removed symbols
+    fn compact(&mut self) {
+        let is_live = |posting: &Posting| is_live(&self.symbols, &self.generations, posting);
+        self.chars.retain(|_, postings| {
+            postings.retain(is_live);
+            !postings.is_empty()
+        });
+        self.pairs.retain(|_, postings| {
+            postings.retain(is_live);
+            !postings.is_empty()
+        });
+        self.postings -= self.stale_postings;
+        self.stale_postings = 0;
+    }
+
+    /// The symbols matching `query`, best first. The last `::` separated part of the query is
+    /// matched against symbol names, and any earlier parts against their containers, so
+    /// `net::Client` finds `Client`s in modules like `net` or `network`.
+    pub fn search(&self, query: &str) -> Vec<&SymbolData> {
+        let mut parts = query.split("::").map(str::trim).collect::<Vec<_>>();
+        let name_query = parts.pop().unwrap_or_default();
+        let name_chars = name_query
+            .chars()
+            .flat_map(char::to_lowercase)
+            .collect::<Vec<_>>();
+
+        // Only the best results are kept, with the worst of them on top of the heap, so it's the
+        // one dropped when a better result comes along
+        let mut best = BinaryHeap::with_capacity(MAX_RESULTS + 1);
+        // Scattered matches are only looked for when there aren't enough close ones
+        for close in [true, false] {
+            if !close && best.len() == MAX_RESULTS {
+                break;
+            }
+            for (id, symbol) in self.candidates(&name_chars, close) {
+                if !parts.is_empty() && !container_matches(&parts, &symbol.container) {
+                    continue;
+                }
+                let Some(score) = fuzzy_score(name_query, &symbol.name) else {
+                    continue;
+                };
+
+                let rank = (
+                    score,
+                    Reverse(symbol.name.len()),
+                    Reverse(symbol.name.as_str()),
+                    Reverse(id),
+                );
+                best.push(Reverse(rank));
+                if best.len() > MAX_RESULTS {
+                    best.pop();
+                }
+            }
+        }
+
+        best.into_sorted_vec()
+            .into_iter()
+            .filter_map(|Reverse((_, _, _, Reverse(id)))| self.symbols[id as usize].as_ref())
+            .collect()
+    }
+
+    /// The symbols that might match `chars`. Close candidates come from the shortest posting list
+    /// of its only character, or of one of its pairs of consecutive characters, keeping only
+    /// those with all of its pairs. With no characters, every symbol is a close candidate.
+    ///
+    /// Otherwise, the candidates are those from the shortest posting list of its characters that
+    /// are missing some pair, which can still match with their characters spread out, like `pt`
+    /// in `Point`. Short queries don't have any.
+    fn candidates(
+        &self,
+        chars: &[char],
+        close: bool,
+    ) -> Box<dyn Iterator<Item = (SymbolId, &SymbolData)> + '_> {
+        let pairs = chars
+            .windows(2)
+            .map(|pair| (pair[0], pair[1]))
+            .collect::<Vec<_>>();
+        let shortest = match chars {
+            [] | [_] if !close => return Box::new(std::iter::empty()),
+            [] => {
+                return Box::new(
+                    self.symbols
+                        .iter()
+                        .enumerate()
+                        .filter_map(|(id, symbol)| Some((id as SymbolId, symbol.as_ref()?))),
+                );
+            }
+            [c] => self.chars.get(c),
+            _ if close => pairs
+                .iter()
+                .map(|pair| self.pairs.get(pair))
+                .min_by_key(|postings| postings.map_or(0, Vec::len))
+                .flatten(),
+            _ => chars
+                .iter()
+                .map(|c| self.chars.get(c))
+                .min_by_key(|postings| postings.map_or(0, Vec::len))
+                .flatten(),
+        };
+
+        Box::new(
+            shortest
+                .into_iter()
+                .flatten()
+                .filter(|posting| is_live(&self.symbols, &self.generations, posting))
+                .filter_map(|posting| {
+                    Some((posting.id, self.symbols[posting.id as usize].as_ref()?))
+                })
+                .filter(move |(_, symbol)| {
+                    let (_, name_pairs) = grams(&symbol.name);
+                    pairs
+                        .iter()
+                        .all(|pair| name_pairs.binary_search(pair).is_ok())
+                        == close
+                }),
+        )
+    }
+}
+
+/// Whether `posting` still refers to the symbol it was added for
+fn is_live(symbols: &[Option<SymbolData>], generations: &[u32], posting: &Posting) -> bool {
+    generations[posting.id as usize] == posting.generation && symbols[posting.id as usize].is_some()
+}
+
+/// The distinct lowercased characters of `name`, and its distinct pairs: characters next to each
+/// other, also once underscores are left out, and every two word starts in order
+fn grams(name: &str) -> (Vec<char>, Vec<(char, char)>) {
+    let name_chars = name.chars().collect::<Vec<_>>();
+    let lowercase = |c: char| c.to_lowercase().next().unwrap_or(c);
+    let chars = name
+        .chars()
+        .flat_map(char::to_lowercase)
+        .collect::<Vec<_>>();
+    let without_underscores = chars
+        .iter()
+        .filter(|c| **c != '_')
+        .copied()
+        .collect::<Vec<_>>();
+    let word_starts = (0..name_chars.len())
+        .filter(|i| name_chars[*i] != '_' && is_word_start(&name_chars, *i))
+        .map(|i| lowercase(name_chars[i]))
+        .collect::<Vec<_>>();
+
+    let mut pairs = chars
+        .windows(2)
+        .chain(without_underscores.windows(2))
+        .map(|pair| (pair[0], pair[1]))
+        .chain(word_starts.iter().enumerate().flat_map(|(i, first)| {
+            word_starts[i + 1..]
+                .iter()
+                .map(move |second| (*first, *second))
+        }))
+        .collect::<Vec<_>>();
+    pairs.sort_unstable();
+    pairs.dedup();
+
+    let mut chars = chars;
+    chars.sort_unstable();
+    chars.dedup();
+    (chars, pairs)
+}
+
+/// Whether each of the query's qualifier parts fuzzily matches a segment of `container`, in order
+fn container_matches(parts: &[&str], container: &[String]) -> bool {
+    let mut segments = container.iter();
+    parts
+        .iter()
+        .filter(|part| !part.is_empty())
+        .all(|part| segments.any(|segment| fuzzy_score(part, segment).is_some()))
+}
+
+/// How well `query` matches `name` as a case-insensitive subsequence, or `None` if it doesn't.
+/// Matches at the start of words (camel humps, after `_`) and runs of consecutive characters
+/// score higher, as do exact and prefix matches.
+pub fn fuzzy_score(query: &str, name: &str) -> Option<i64> {
+    if query.eq_ignore_ascii_case(name) {
+        return Some(i64::MAX);
+    }
+
+    // Jumping ahead to word starts can skip characters needed later, so fall back to a plain
+    // subsequence match
+    let name_chars = name.chars().collect::<Vec<_>>();
+    let mut score = subsequence_score(query, &name_chars, true)
+        .or_else(|| subsequence_score(query, &name_chars, false))?;
+    if name.to_lowercase().starts_with(&query.to_lowercase()) {
+        score += 100;
+    }
+
+    Some(score)
+}
+
+fn subsequence_score(query: &str, name_chars: &[char], prefer_word_starts: bool) -> Option<i64> {
+    let mut score = 0;
+    let mut position = 0;
+    let mut previous_match = None;
+
+    for query_char in query.chars().flat_map(char::to_lowercase) {
+        let matches = |i: &usize| {
+            name_chars[*i]
+                .to_lowercase()
+                .eq(std::iter::once(query_char))
+        };
+
+        // Prefer continuing a run, then the start of a word, then anything
+        let next = (position..name_chars.len()).find(matches)?;
+        let found = if !prefer_word_starts || previous_match == Some(next.wrapping_sub(1)) {
+            next
+        } else {
+            (next..name_chars.len())
+                .filter(matches)
+                .find(|i| is_word_start(name_chars, *i))
+                .unwrap_or(next)
+        };
+
+        score += 1;
+        if is_word_start(name_chars, found) {
+            score += 10;
+        }
+        if previous_match.is_some_and(|previous| previous + 1 == found) {
+            score += 5;
+        }
+
+        previous_match = Some(found);
+        position = found + 1;
+    }
+
+    Some(score)
+}
+
+fn is_word_start(chars: &[char], i: usize) -> bool {
+    let Some(previous) = i.checked_sub(1).map(|previous| chars[previous]) else {
+        return true;
+    };
+
+    previous == '_' || (previous.is_lowercase() && chars[i].is_uppercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::{Range, Url};
+
+    use super::*;
+
+    fn symbol(container: &[&str], name: &str) -> SymbolData {
+        SymbolData {
+            name: name.to_string(),
+            kind: SymbolKind::STRUCT,
+            container: container
+                .iter()
+                .map(|segment| segment.to_string())
+                .collect(),
+            location: Location::new(Url::parse("file:///lib.rs").unwrap(), Range::default()),
+        }
+    }
+
+    fn names<'a>(results: &[&'a SymbolData]) -> Vec<&'a str> {
+        results.iter().map(|symbol| symbol.name.as_str()).collect()
+    }
+
+    #[test]
+    fn fuzzy_score_matches_subsequences_only() {
+        assert!(fuzzy_score("nc", "NetClient").is_some());
+        assert!(fuzzy_score("NETCLIENT", "NetClient").is_some());
+        assert!(fuzzy_score("ltc", "NetClient").is_none());
+        assert!(fuzzy_score("nets", "NetClient").is_none());
+        assert!(fuzzy_score("", "NetClient").is_some());
+    }
+
+    #[test]
+    fn fuzzy_score_prefers_exact_prefix_and_word_starts() {
+        let score = |query, name| fuzzy_score(query, name).unwrap();
+        assert_eq!(score("client", "Client"), i64::MAX);
+        assert!(score("cli", "Client") > score("cli", "NetClient"));
+        // `c` and `l` are both word starts in `CacheLoader`, but not in `Cell`
+        assert!(score("cl", "CacheLoader") > score("cl", "Xcell"));
+        assert!(score("read_to", "read_to_string") > score("read_to", "ready_two"));
+    }
+
+    #[test]
+    fn fuzzy_score_falls_back_when_word_starts_skip_needed_characters() {
+        // Jumping to the `N` of `Node` would leave nothing for the second `n`
+        assert!(fuzzy_score("nn", "nameNode").is_some());
+        assert!(fuzzy_score("nan", "nameNode").is_some());
+    }
+
+    #[test]
+    fn search_ranks_and_filters_by_container() {
+        let mut index = SymbolIndex::default();
+        index.insert(symbol(&["crate", "net"], "Client"));
+        index.insert(symbol(&["crate", "db"], "Client"));
+        index.insert(symbol(&["crate", "net"], "NetworkClient"));
+        index.insert(symbol(&["crate"], "Config"));
+
+        assert_eq!(
+            names(&index.search("client")),
+            ["Client", "Client", "NetworkClient"]
+        );
+        assert_eq!(names(&index.search("nc")), ["NetworkClient"]);
+        assert_eq!(names(&index.search("c")).len(), 4);
+
+        let qualified = index.search("net::Cli");
+        assert_eq!(names(&qualified), ["Client", "NetworkClient"]);
+        assert!(qualified.iter().all(|symbol| symbol.container[1] == "net"));
+    }
+
+    #[test]
+    fn search_forgets_removed_symbols_and_reuses_their_ids() {
+        let mut index = SymbolIndex::default();
+        let client = index.insert(symbol(&["crate"], "Client"));
+        index.insert(symbol(&["crate"], "Config"));
+        index.remove(client);
+
+        assert!(index.search("client").is_empty());
+        assert_eq!(names(&index.search("")), ["Config"]);
+        assert_eq!(index.insert(symbol(&["crate"], "Server")), client);
+        assert_eq!(names(&index.search("srv")), ["Server"]);
+        assert_eq!(names(&index.search("cl")), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn removing_symbols_compacts_their_postings() {
+        let mut index = SymbolIndex::default();
+        let ids = (0..10)
+            .map(|i| index.insert(symbol(&["crate"], &format!("Client{i}"))))
+            .collect::<Vec<_>>();
+        for id in &ids[..8] {
+            index.remove(*id);
+        }
+
+        assert!(index.stale_postings * 2 <= index.postings);
+        assert_eq!(names(&index.search("client")), ["Client8", "Client9"]);
+        let postings = index.pairs[&('c', 'l')].len();
+        assert!(postings < 10, "{postings} postings for 2 symbols");
+    }
+
+    #[test]
+    fn pairs_are_adjacent_characters_and_word_starts() {
+        let (_, pairs) = grams("Net_client");
+        assert_eq!(
+            pairs,
+            [
+                ('_', 'c'),
+                ('c', 'l'),
+                ('e', 'n'),
+                ('e', 't'),
+                ('i', 'e'),
+                ('l', 'i'),
+                ('n', 'c'),
+                ('n', 'e'),
+                ('n', 't'),
+                ('t', '_'),
+                ('t', 'c'),
+            ]
+        );
+
+        // Long names get a posting per pair of neighbours rather than per pair of characters
+        let (_, pairs) = grams("abcdefghijklmnopqrstuvwxyz");
+        assert_eq!(pairs.len(), 25);
+    }
+
+    #[test]
+    fn close_candidates_have_runs_word_starts_and_skipped_underscores() {
+        let mut index = SymbolIndex::default();
+        index.insert(symbol(&["crate"], "read_to_string"));
+        index.insert(symbol(&["crate"], "ReadToString"));
+        index.insert(symbol(&["crate"], "thread_stop"));
+
+        let close = |query: &str| {
+            let chars = query.chars().collect::<Vec<_>>();
+            let mut names = index
+                .candidates(&chars, true)
+                .map(|(_, symbol)| symbol.name.as_str())
+                .collect::<Vec<_>>();
+            names.sort_unstable();
+            names
+        };
+        assert_eq!(close("readto"), ["ReadToString", "read_to_string"]);
+        assert_eq!(close("rts"), ["ReadToString", "read_to_string"]);
+        assert_eq!(close("rstr"), ["ReadToString", "read_to_string"]);
+        assert_eq!(close("dst"), ["thread_stop"]);
+
+        // Scattered matches come after close ones, or on their own when there are none
+        assert_eq!(
+            names(&index.search("readto")),
+            ["ReadToString", "read_to_string", "thread_stop"]
+        );
+        assert_eq!(names(&index.search("tp")), ["thread_stop"]);
+    }
+
+    #[test]
+    fn search_keeps_only_the_best_results() {
+        let mut index = SymbolIndex::default();
+        for i in 0..MAX_RESULTS * 2 {
+            index.insert(symbol(&["crate"], &format!("item_{i}")));
+        }
+        index.insert(symbol(&["crate"], "item"));
+
+        let results = index.search("item");
+        assert_eq!(results.len(), MAX_RESULTS);
+        assert_eq!(results[0].name, "item");
+        assert_eq!(index.search("").len(), MAX_RESULTS);
+    }
+}
@@ -0,0 +1,238 @@
+use lsp_types::{DocumentSymbol, SymbolKind, SymbolTag};
+use rust_analyzer_syntax::ast::{self, HasAttrs, HasName};
+use rust_analyzer_syntax::{AstNode, SyntaxKind, SyntaxNode, TextRange};
+
+use crate::database::{FileData, ItemKind};
+use crate::hover::signature;
+use crate::utils::{PositionIndex, range};
+
+/// The outline of `file`: every item, with the members of modules, impls, traits, structs and enums
+/// nested inside them
+pub fn document_symbols(file: &FileData) -> Vec<DocumentSymbol> {
+    item_symbols(&file.ast.syntax_node(), &file.index)
+}
+
+/// The symbols for the items directly inside `node`, such as a file or an item list
+fn item_symbols(node: &SyntaxNode, index: &PositionIndex) -> Vec<DocumentSymbol> {
+    let mut symbols = vec![];
+    for child in node.children() {
+        // Extern blocks don't have names of their own, so their items are listed alongside
+        if let Some(extern_block) = ast::ExternBlock::cast(child.clone()) {
+            if let Some(items) = extern_block.extern_item_list() {
+                symbols.extend(item_symbols(items.syntax(), index));
+            }
+            continue;
+        }
+
+        if let Some(symbol) = item_symbol(&child, index) {
+            symbols.push(symbol);
+        }
+    }
+    symbols
+}
+
+fn item_symbol(node: &SyntaxNode, index: &PositionIndex) -> Option<DocumentSymbol> {
+    let in_assoc_item_list =
+        node.parent().map(|parent| parent.kind()) == Some(SyntaxKind::ASSOC_ITEM_LIST);
+
+    let children = match node.kind() {
+        SyntaxKind::MODULE => ast::Module::cast(node.clone())?
+            .item_list()
+            .map(|items| item_symbols(items.syntax(), index)),
+        SyntaxKind::IMPL => return impl_symbol(ast::Impl::cast(node.clone())?, index),
+        SyntaxKind::TRAIT => ast::Trait::cast(node.clone())?
+            .assoc_item_list()
+            .map(|items| item_symbols(items.syntax(), index)),
+        SyntaxKind::STRUCT | SyntaxKind::UNION => node
+            .children()
+            .find_map(ast::RecordFieldList::cast)
+            .map(|fields| {
+                fields
+                    .fields()
+                    .filter_map(|field| child_symbol(&field, SymbolKind::FIELD, index))
+                    .collect()
+            }),
+        SyntaxKind::ENUM => ast::Enum::cast(node.clone())?
+            .variant_list()
+            .map(|variants| {
+                variants
+                    .variants()
+                    .filter_map(|variant| child_symbol(&variant, SymbolKind::ENUM_MEMBER, index))
+                    .collect()
+            }),
+        _ => None,
+    };
+
+    // The same kinds as workspace symbols use
+    let kind = match ItemKind::from_syntax_kind(node.kind()) {
+        Some(ItemKind::Fn) if in_assoc_item_list => SymbolKind::METHOD,
+        Some(kind) => kind.symbol_kind(),
+        None if node.kind() == SyntaxKind::MODULE => SymbolKind::MODULE,
+        None => return None,
+    };
+
+    let name = ast::AnyHasName::cast(node.clone())?.name()?;
+    let detail = (node.kind() == SyntaxKind::FN).then(|| signature(node));
+    Some(symbol(
+        name.text_non_mutable().to_string(),
+        detail,
+        kind,
+        node,
+        name.syntax().text_range(),
+        children,
+        index,
+    ))
+}
+
+/// Impls are named after what they implement, like `impl Display for Foo`
+fn impl_symbol(impl_: ast::Impl, index: &PositionIndex) -> Option<DocumentSymbol> {
+    let self_ty = impl_.self_ty()?;
+    let name = match impl_.trait_() {
+        Some(trait_) => format!("impl {trait_} for {self_ty}"),
+        None => format!("impl {self_ty}"),
+    };
+    let children = impl_
+        .assoc_item_list()
+        .map(|items| item_symbols(items.syntax(), index));
+
+    Some(symbol(
+        name,
+        None,
+        SymbolKind::OBJECT,
+        impl_.syntax(),
+        self_ty.syntax().text_range(),
+        children,
+        index,
+    ))
+}
+
+fn child_symbol(
+    child: &(impl HasName + HasAttrs),
+    kind: SymbolKind,
+    index: &PositionIndex,
+) -> Option<DocumentSymbol> {
+    let name = child.name()?;
+    Some(symbol(
+        name.text_non_mutable().to_string(),
+        None,
+        kind,
+        child.syntax(),
+        name.syntax().text_range(),
+        None,
+        index,
+    ))
+}
+
+fn symbol(
+    name: String,
+    detail: Option<String>,
+    kind: SymbolKind,
+    node: &SyntaxNode,
+    selection_range: TextRange,
+    children: Option<Vec<DocumentSymbol>>,
+    index: &PositionIndex,
+) -> DocumentSymbol {
+    let is_deprecated = ast::AnyHasAttrs::cast(node.clone()).is_some_and(|item| {
+        item.attrs().any(|attr| {
+            attr.path()
+                .is_some_and(|path| path.syntax().text() == "deprecated")
+        })
+    });
+
+    #[allow(deprecated)]
+    DocumentSymbol {
+        name,
+        detail,
+        kind,
+        tags: is_deprecated.then(|| vec![SymbolTag::DEPRECATED]),
+        deprecated: None,
+        range: range(node.text_range(), index),
+        selection_range: range(selection_range, index),
+        children,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::FileUrl;
+    use crate::test_utils::{self, TempDir};
+
+    /// Each symbol as its name, kind and whether it's deprecated, with its children indented below
+    fn outline(symbols: &[DocumentSymbol], depth: usize, lines: &mut Vec<String>) {
+        for symbol in symbols {
+            let deprecated = if symbol.tags.is_some() {
+                " deprecated"
+            } else {
+                ""
+            };
+            lines.push(format!(
+                "{}{} {:?}{deprecated}",
+                "  ".repeat(depth),
+                symbol.name,
+                symbol.kind
+            ));
+            outline(symbol.children.as_deref().unwrap_or(&[]), depth + 1, lines);
+        }
+    }
+
+    #[test]
+    fn nests_members_under_their_items() {
+        let dir = TempDir::new(
+            "document-symbols",
+            &[(
+                "lib.rs",
+                "mod shapes {
+    pub struct Point { x: u8, y: u8 }
+    #[deprecated]
+    pub enum Old { A, B }
+}
+
+trait Area {
+    fn area(&self) -> u8;
+}
+
+impl Area for shapes::Point {
+    fn area(&self) -> u8 { 0 }
+}
+
+impl shapes::Point {
+    #[deprecated = \"use area\"]
+    const ZERO: u8 = 0;
+}
+
+extern \"C\" {
+    fn abs(x: i32) -> i32;
+    static errno: i32;
+}
+",
+            )],
+        );
+        let (mut db, _client) = test_utils::database();
+        let file = FileUrl::from_path(&dir.0.join("lib.rs")).unwrap();
+        db.load_file(&file).unwrap();
+
+        let mut lines = vec![];
+        outline(&document_symbols(&db.files[&file]), 0, &mut lines);
+        assert_eq!(
+            lines,
+            [
+                "shapes Module",
+                "  Point Struct",
+                "    x Field",
+                "    y Field",
+                "  Old Enum deprecated",
+                "    A EnumMember",
+                "    B EnumMember",
+                "Area Interface",
+                "  area Method",
+                "impl Area for shapes::Point Object",
+                "  area Method",
+                "impl shapes::Point Object",
+                "  ZERO Constant deprecated",
+                "abs Function",
+                "errno Variable",
+            ]
+        );
+    }
+}
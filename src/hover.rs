@@ -0,0 +1,353 @@
+use lsp_types::{Hover, HoverContents, HoverParams, Location, MarkupContent, MarkupKind};
+use rust_analyzer_syntax::ast::{self, CommentPlacement};
+use rust_analyzer_syntax::{AstNode, AstToken, NodeOrToken, SyntaxKind, SyntaxNode, TextSize};
+use snafu::{OptionExt, Whatever};
+
+use crate::database::doc_links::{
+    DocLinkParser, location_url, replace_doc_self_type, resolve_doc_link,
+};
+use crate::database::resolve::{find_by_name, module_at, path_at_position, resolve_at};
+use crate::database::scope::find_local;
+use crate::database::{Database, FileData, FileUrl, ModulePath};
+use crate::utils::range;
+
+/// The signature and documentation of whatever is under the cursor, as markdown
+pub fn hover(db: &mut Database, params: &HoverParams) -> Result<Option<Hover>, Whatever> {
+    let position = &params.text_document_position_params;
+    let text_doc = db
+        .get_file(&FileUrl::from_url(position.text_document.uri.clone())?)
+        .whatever_context("failed to get hover request file")?;
+    let path = path_at_position(text_doc, position.position)?;
+
+    // Locals have no documentation, and without types there's nothing else to show
+    if find_local(&path.token).is_some() {
+        return Ok(None);
+    }
+    let hover_range = range(path.token.text_range(), &text_doc.index);
+
+    let mut locations = resolve_at(db, &path)
+        .map(|resolution| resolution.locations(db))
+        .unwrap_or_default();
+    if locations.is_empty()
+        && let Some(name) = path.segments.last()
+    {
+        locations = find_by_name(db, &path.module, name);
+    }
+
+    let sections = locations
+        .iter()
+        .filter_map(|location| render_location(db, location))
+        .collect::<Vec<_>>();
+    if sections.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(Hover {
+        contents: HoverContents::Markup(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value: sections.join("\n\n---\n\n"),
+        }),
+        range: Some(hover_range),
+    }))
+}
+
+/// Render the definition at `location`, which must already be loaded
+fn render_location(db: &Database, location: &Location) -> Option<String> {
+    let file_url = FileUrl::from_url(location.uri.clone()).ok()?;
+    let file = db.files.get(&file_url)?;
+    let node = node_at_range(file, location.range)?;
+
+    let mut docs = vec![];
+    let signature = if node.kind() == SyntaxKind::SOURCE_FILE {
+        // A file module's outer docs are on its `mod` declaration in the parent
+        if let Some(declaration) = module_declaration(db, file) {
+            collect_docs(&declaration, CommentPlacement::Outer, &mut docs);
+        }
+        collect_docs(&node, CommentPlacement::Inner, &mut docs);
+
+        let name = file
+            .module_path
+            .segments
+            .last()
+            .unwrap_or(&file.module_path.crate_);
+        format!("mod {name}")
+    } else {
+        collect_docs(&node, CommentPlacement::Outer, &mut docs);
+        if let Some(item_list) =
+            ast::Module::cast(node.clone()).and_then(|module| module.item_list())
+        {
+            collect_docs(item_list.syntax(), CommentPlacement::Inner, &mut docs);
+        }
+
+        signature(&node)
+    };
+
+    let mut markdown = format!("```rust\n{signature}\n```");
+    let module = module_at(&file.module_path, &node);
+    let docs = link_docs(db, &module, &node, strip_indentation(&docs));
+    if !docs.is_empty() {
+        markdown.push_str("\n\n");
+        markdown.push_str(&docs);
+    }
+
+    Some(markdown)
+}
+
+/// Point the intra-doc links in `docs` (belonging to `node` in `module`) at the files they resolve
+/// to, so they can be clicked
+fn link_docs(db: &Database, module: &ModulePath, node: &SyntaxNode, mut docs: String) -> String {
+    let mut links = vec![];
+    DocLinkParser::default().parse(&docs, 0, &mut links);
+
+    for link in links.into_iter().rev() {
+        let Some(location) = replace_doc_self_type(node, link.path)
+            .and_then(|path| resolve_doc_link(db, module, &path))
+        else {
+            continue;
+        };
+
+        let url = location_url(&location);
+        let replacement = match link.wrap {
+            true => format!("({url})"),
+            false => url.to_string(),
+        };
+        docs.replace_range(link.rewrite, &replacement);
+    }
+
+    docs
+}
+
+/// The outermost node spanning exactly `range`
+pub fn node_at_range(file: &FileData, range: lsp_types::Range) -> Option<SyntaxNode> {
+    let text_range = file.index.text_range(range)?;
+    let node = match file.ast.syntax_node().covering_element(text_range) {
+        NodeOrToken::Node(node) => node,
+        NodeOrToken::Token(token) => token.parent()?,
+    };
+
+    node.ancestors()
+        .take_while(|node| node.text_range() == text_range)
+        .last()
+}
+
+/// The `mod` declaration in the parent file that includes `file`
+fn module_declaration(db: &Database, file: &FileData) -> Option<SyntaxNode> {
+    let parent = db.files.get(file.parent.as_ref()?)?;
+    let include = parent
+        .modules
+        .iter()
+        .find(|include| include.module == file.module_path)?;
+
+    node_at_range(parent, include.range)
+}
+
+/// The header of an item: everything after its attributes and doc comments, up to its body
+pub fn signature(node: &SyntaxNode) -> String {
+    let node_start = node.text_range().start();
+    let start = node
+        .children_with_tokens()
+        .find(|element| {
+            !matches!(
+                element.kind(),
+                SyntaxKind::ATTR | SyntaxKind::COMMENT | SyntaxKind::WHITESPACE
+            )
+        })
+        .map(|element| element.text_range().start())
+        .unwrap_or(node_start);
+    let end = node
+        .children_with_tokens()
+        .find(|element| match element.kind() {
+            SyntaxKind::BLOCK_EXPR
+            | SyntaxKind::RECORD_FIELD_LIST
+            | SyntaxKind::VARIANT_LIST
+            | SyntaxKind::ASSOC_ITEM_LIST
+            | SyntaxKind::ITEM_LIST
+            | SyntaxKind::SEMICOLON => true,
+            SyntaxKind::EQ => matches!(node.kind(), SyntaxKind::CONST | SyntaxKind::STATIC),
+            SyntaxKind::TOKEN_TREE => {
+                matches!(node.kind(), SyntaxKind::MACRO_RULES | SyntaxKind::MACRO_DEF)
+            }
+            _ => false,
+        })
+        .map(|element| element.text_range().start())
+        .unwrap_or_else(|| node.text_range().end());
+
+    let text = node.text().to_string();
+    let relative = |offset: TextSize| usize::from(offset - node_start);
+    text[relative(start)..relative(end)].trim().to_string()
+}
+
+/// A line of documentation, and whether it came from a doc comment rather than an attribute
+type DocLine = (String, bool);
+
+/// Push the lines of every doc comment and `#[doc = ".."]` attribute directly on `node` with the
+/// given placement
+fn collect_docs(node: &SyntaxNode, placement: CommentPlacement, docs: &mut Vec<DocLine>) {
+    for element in node.children_with_tokens() {
+        let (text, is_comment) = match element {
+            NodeOrToken::Token(token) => {
+                let text = ast::Comment::cast(token)
+                    .filter(|comment| comment.kind().doc == Some(placement))
+                    .and_then(|comment| comment.doc_comment().map(str::to_string));
+                (text, true)
+            }
+            NodeOrToken::Node(node) => {
+                let text = ast::Attr::cast(node)
+                    .filter(|attr| attr.kind().is_inner() == (placement == CommentPlacement::Inner))
+                    .and_then(|attr| doc_attr(&attr));
+                (text, false)
+            }
+        };
+
+        if let Some(text) = text {
+            docs.extend(text.split('\n').map(|line| (line.to_string(), is_comment)));
+        }
+    }
+}
+
+/// The string in `#[doc = "..."]`
+fn doc_attr(attr: &ast::Attr) -> Option<String> {
+    let path = attr.path()?;
+    if path.qualifier().is_some() || path.segment()?.name_ref()?.text() != "doc" {
+        return None;
+    }
+
+    let ast::Expr::Literal(literal) = attr.meta()?.expr()? else {
+        return None;
+    };
+    let string = ast::String::cast(literal.token())?;
+    Some(string.value().ok()?.to_string())
+}
+
+/// Join doc lines, removing the indentation the doc comments share (usually the space after `///`)
+/// like rustdoc does. Attribute docs are taken as they are.
+fn strip_indentation(lines: &[DocLine]) -> String {
+    let indentation = lines
+        .iter()
+        .filter(|(line, is_comment)| *is_comment && !line.trim().is_empty())
+        .map(|(line, _)| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+
+    lines
+        .iter()
+        .map(|(line, is_comment)| match is_comment {
+            true => line.get(indentation..).unwrap_or("").trim_end(),
+            false => line.trim_end(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_analyzer_syntax::{Edition, SourceFile};
+
+    use super::*;
+    use crate::test_utils::{self, TempDir};
+
+    /// The first node of `kind` in `text`
+    fn node(text: &str, kind: SyntaxKind) -> SyntaxNode {
+        SourceFile::parse(text, Edition::Edition2021)
+            .syntax_node()
+            .descendants()
+            .find(|node| node.kind() == kind)
+            .unwrap()
+    }
+
+    fn docs(text: &str, kind: SyntaxKind) -> String {
+        let mut docs = vec![];
+        collect_docs(&node(text, kind), CommentPlacement::Outer, &mut docs);
+        strip_indentation(&docs)
+    }
+
+    #[test]
+    fn signatures_stop_at_the_body() {
+        let text = "/// Docs\n#[inline]\npub fn add(a: u8, b: u8) -> u8 { a + b }";
+        assert_eq!(
+            signature(&node(text, SyntaxKind::FN)),
+            "pub fn add(a: u8, b: u8) -> u8"
+        );
+        let text = "trait Tr { fn required(&self) -> u8; }";
+        assert_eq!(
+            signature(&node(text, SyntaxKind::FN)),
+            "fn required(&self) -> u8"
+        );
+        let text = "pub struct Point<T> where T: Copy { x: T }";
+        assert_eq!(
+            signature(&node(text, SyntaxKind::STRUCT)),
+            "pub struct Point<T> where T: Copy"
+        );
+        let text = "const LIMIT: usize = 3;";
+        assert_eq!(
+            signature(&node(text, SyntaxKind::CONST)),
+            "const LIMIT: usize"
+        );
+        let text = "macro_rules! mk { () => {} }";
+        assert_eq!(
+            signature(&node(text, SyntaxKind::MACRO_RULES)),
+            "macro_rules! mk"
+        );
+    }
+
+    #[test]
+    fn doc_comments_lose_their_shared_indentation() {
+        let text =
+            "///  Summary\n///\n///  ```\n///  if x {\n///      y\n///  }\n///  ```\nfn f() {}";
+        assert_eq!(
+            docs(text, SyntaxKind::FN),
+            "Summary\n\n```\nif x {\n    y\n}\n```"
+        );
+    }
+
+    #[test]
+    fn doc_attributes_mix_with_doc_comments() {
+        let text = "/// First\n#[doc = \"  Second\"]\n#[allow(dead_code)]\n/// Third\nfn f() {}";
+        assert_eq!(docs(text, SyntaxKind::FN), "First\n  Second\nThird");
+
+        // Inner docs belong to what they're in, not to what comes next
+        let text = "mod m {\n    //! Inner\n    /// Outer\n    fn f() {}\n}";
+        assert_eq!(docs(text, SyntaxKind::FN), "Outer");
+        let mut inner = vec![];
+        collect_docs(
+            &node(text, SyntaxKind::ITEM_LIST),
+            CommentPlacement::Inner,
+            &mut inner,
+        );
+        assert_eq!(strip_indentation(&inner), "Inner");
+    }
+
+    #[test]
+    fn doc_links_point_at_their_definitions() {
+        let dir = TempDir::new(
+            "hover-links",
+            &[(
+                "lib.rs",
+                "pub struct Target;\n\n/// See [`Target`] and [missing].\npub fn f() {}\n",
+            )],
+        );
+        let (mut db, _client) = test_utils::database();
+        let file = FileUrl::from_path(&dir.0.join("lib.rs")).unwrap();
+        db.load_file(&file).unwrap();
+
+        let data = &db.files[&file];
+        let node = data
+            .ast
+            .syntax_node()
+            .descendants()
+            .find(|node| node.kind() == SyntaxKind::FN)
+            .unwrap();
+        let docs = link_docs(
+            &db,
+            &data.module_path,
+            &node,
+            "See [`Target`] and [missing].".into(),
+        );
+        assert_eq!(
+            docs,
+            format!("See [`Target`]({}#L1) and [missing].", file.url())
+        );
+    }
+}
@@ -1,45 +1,92 @@
+pub mod auto_import;
 pub mod code_action;
+pub mod completion;
 pub mod database;
+pub mod document_symbol;
+pub mod hover;
 pub mod notification;
 pub mod request;
+pub mod semantic_tokens;
+#[cfg(test)]
+mod test_utils;
 pub mod utils;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use lsp_server::{Connection, Message};
+use lsp_server::{Connection, ErrorCode, Message, Response};
 use lsp_types::{
-    CodeActionProviderCapability, DiagnosticOptions, DiagnosticServerCapabilities,
-    InitializeParams, OneOf, ServerCapabilities, TextDocumentSyncCapability, TextDocumentSyncKind,
-    WorkDoneProgressOptions,
+    CodeActionProviderCapability, CompletionOptions, DiagnosticOptions,
+    DiagnosticServerCapabilities, DocumentLinkOptions, HoverProviderCapability,
+    ImplementationProviderCapability, InitializeParams, OneOf, RenameOptions,
+    SemanticTokensFullOptions, SemanticTokensOptions, SemanticTokensServerCapabilities,
+    ServerCapabilities, TextDocumentSyncCapability, TextDocumentSyncKind, WorkDoneProgressOptions,
 };
 use snafu::{OptionExt, ResultExt, Whatever};
 
 use crate::database::Database;
-use crate::utils::OrLog;
+use crate::database::symbol_index::SymbolIndex;
+use crate::utils::{OrLog, PositionEncoding};
 
 fn main() {
     let (connection, io_threads) = Connection::stdio();
 
+    let (rid, params) = connection
+        .initialize_start()
+        .expect("initialization failed");
+    let mut initialize_params =
+        serde_json::from_value::<InitializeParams>(params).unwrap_or_default();
+    let position_encoding = PositionEncoding::negotiate(
+        initialize_params
+            .capabilities
+            .general
+            .as_ref()
+            .and_then(|general| general.position_encodings.as_deref()),
+    );
+
     let server_capabilities = ServerCapabilities {
+        position_encoding: Some(position_encoding.kind()),
         code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
         definition_provider: Some(OneOf::Left(true)),
+        implementation_provider: Some(ImplementationProviderCapability::Simple(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec![":".to_string()]),
+            ..Default::default()
+        }),
+        references_provider: Some(OneOf::Left(true)),
+        document_highlight_provider: Some(OneOf::Left(true)),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        document_link_provider: Some(DocumentLinkOptions {
+            resolve_provider: Some(false),
+            work_done_progress_options: WorkDoneProgressOptions::default(),
+        }),
+        rename_provider: Some(OneOf::Right(RenameOptions {
+            prepare_provider: Some(true),
+            work_done_progress_options: WorkDoneProgressOptions::default(),
+        })),
         diagnostic_provider: Some(DiagnosticServerCapabilities::Options(DiagnosticOptions {
             identifier: None,
             inter_file_dependencies: true,
-            workspace_diagnostics: false,
+            workspace_diagnostics: true,
             work_done_progress_options: WorkDoneProgressOptions {
                 work_done_progress: Some(false),
             },
         })),
-        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        workspace_symbol_provider: Some(OneOf::Left(true)),
+        semantic_tokens_provider: Some(SemanticTokensServerCapabilities::SemanticTokensOptions(
+            SemanticTokensOptions {
+                work_done_progress_options: WorkDoneProgressOptions::default(),
+                legend: semantic_tokens::legend(),
+                range: Some(true),
+                full: Some(SemanticTokensFullOptions::Delta { delta: Some(true) }),
+            },
+        )),
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(
+            TextDocumentSyncKind::INCREMENTAL,
+        )),
         ..Default::default()
     };
 
-    let (rid, params) = connection
-        .initialize_start()
-        .expect("initialization failed");
-    let mut initialize_params =
-        serde_json::from_value::<InitializeParams>(params).unwrap_or_default();
     connection
         .initialize_finish(
             rid,
@@ -58,34 +105,46 @@ fn main() {
         connection,
         initialize_params,
         workspace_folders,
+        position_encoding,
         files: HashMap::default(),
         modules: HashMap::default(),
-        type_defs: HashMap::default(),
-        function_defs: HashMap::default(),
+        items: HashMap::default(),
+        associated_items: HashMap::default(),
+        name_refs: HashMap::default(),
+        symbols: SymbolIndex::default(),
+        packages: vec![],
+        module_files: HashMap::default(),
+        updating: HashSet::default(),
     };
 
     db.log_info("Rust-Navigator Loaded");
 
-    let workspace_folders = std::mem::take(&mut db.workspace_folders);
-
-    for root in &workspace_folders {
-        let Some(path) = root
-            .uri
-            .to_file_path()
-            .ok()
-            .with_whatever_context::<_, _, Whatever>(|| {
-                format!("failed to convert root URI {} to file path", root.uri)
-            })
-            .or_log(&db)
-        else {
-            continue;
-        };
-        if let Err(err) = database::file::find_rust_files(&mut db, &path) {
+    let roots = db
+        .workspace_folders
+        .iter()
+        .filter_map(|root| {
+            root.uri
+                .to_file_path()
+                .ok()
+                .with_whatever_context::<_, _, Whatever>(|| {
+                    format!("failed to convert root URI {} to file path", root.uri)
+                })
+                .or_log(&db)
+        })
+        .collect::<Vec<_>>();
+
+    // Packages decide how files are parsed and which crate they're in, so they go first
+    for path in &roots {
+        if let Err(err) = database::manifest::find_packages(&mut db, path) {
             db.log_error(&format!("Rust-Navigator ERROR: {err}"));
         }
     }
 
-    db.workspace_folders = workspace_folders;
+    for path in &roots {
+        if let Err(err) = database::file::find_rust_files(&mut db, path) {
+            db.log_error(&format!("Rust-Navigator ERROR: {err}"));
+        }
+    }
 
     let mut shutdown = false;
     loop {
@@ -102,14 +161,68 @@ fn main() {
             Message::Request(request) => {
                 shutdown = db.connection.handle_shutdown(&request).unwrap_or_default();
 
-                match &request.method[..] {
+                let id = request.id.clone();
+                let result = match &request.method[..] {
                     "textDocument/codeAction" => {
-                        _ = request::text_document::code_action(&mut db, request).or_log(&db);
+                        request::text_document::code_action(&mut db, request)
                     }
                     "textDocument/definition" => {
-                        _ = request::text_document::definition(&mut db, request).or_log(&db);
+                        request::text_document::definition(&mut db, request)
+                    }
+                    "textDocument/hover" => request::text_document::hover(&mut db, request),
+                    "textDocument/completion" => {
+                        request::text_document::completion(&mut db, request)
+                    }
+                    "textDocument/implementation" => {
+                        request::text_document::implementation(&mut db, request)
+                    }
+                    "textDocument/references" => {
+                        request::text_document::references(&mut db, request)
+                    }
+                    "textDocument/documentLink" => {
+                        request::text_document::document_link(&mut db, request)
+                    }
+                    "textDocument/documentSymbol" => {
+                        request::text_document::document_symbol(&mut db, request)
+                    }
+                    "textDocument/semanticTokens/full" => {
+                        request::text_document::semantic_tokens_full(&mut db, request)
+                    }
+                    "textDocument/semanticTokens/full/delta" => {
+                        request::text_document::semantic_tokens_full_delta(&mut db, request)
+                    }
+                    "textDocument/semanticTokens/range" => {
+                        request::text_document::semantic_tokens_range(&mut db, request)
+                    }
+                    "textDocument/documentHighlight" => {
+                        request::text_document::document_highlight(&mut db, request)
+                    }
+                    "textDocument/prepareRename" => {
+                        request::text_document::prepare_rename(&mut db, request)
+                    }
+                    "textDocument/rename" => request::text_document::rename(&mut db, request),
+                    "textDocument/diagnostic" => {
+                        request::text_document::diagnostic(&mut db, request)
                     }
-                    _ => (),
+                    "workspace/diagnostic" => request::workspace::diagnostic(&mut db, request),
+                    "workspace/symbol" => request::workspace::symbol(&mut db, request),
+                    _ => Ok(()),
+                };
+
+                // Clients wait for a response to every request, so failures are reported back
+                // instead of only logged
+                if let Err(err) = result {
+                    db.log_error(&format!("{err}"));
+                    _ = db
+                        .connection
+                        .sender
+                        .send(Message::Response(Response::new_err(
+                            id,
+                            ErrorCode::RequestFailed as i32,
+                            err.to_string(),
+                        )))
+                        .whatever_context::<_, Whatever>("failed to send error response")
+                        .or_log(&db);
                 }
             }
             Message::Response(_response) => todo!(),
@@ -12,7 +12,7 @@ pub fn did_open(db: &mut Database, notification: Notification) -> Result<(), Wha
 
     let path = FileUrl::from_url(params.text_document.uri)
         .whatever_context("didOpen file URI failed to convert to a path")?;
-    db.files.entry(path.clone()).or_default();
+    db.files.entry(path.clone()).or_default().is_open = true;
     db.update_file(
         &path,
         params.text_document.version,
@@ -26,10 +26,14 @@ pub fn did_change(db: &mut Database, notification: Notification) -> Result<(), W
     let params = serde_json::from_value::<DidChangeTextDocumentParams>(notification.params)
         .whatever_context("received invalid textDocument/didChange params")?;
 
-    let src = &params.content_changes[0].text;
     let file_url = FileUrl::from_url(params.text_document.uri)
         .whatever_context("didChange file URI failed to convert to a path")?;
-    db.update_file(&file_url, params.text_document.version, src);
+    db.edit_file(
+        &file_url,
+        params.text_document.version,
+        params.content_changes,
+    )
+    .whatever_context("failed to apply didChange edits")?;
 
     Ok(())
 }
@@ -1 +1,2 @@
 pub mod text_document;
+pub mod workspace;
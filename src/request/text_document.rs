@@ -1,11 +1,38 @@
-use line_index::{TextRange, TextSize};
+use std::collections::HashMap;
+
 use lsp_server::{ErrorCode, Message, Request, Response, ResponseError};
-use lsp_types::{CodeActionParams, GotoDefinitionParams};
+use lsp_types::request::{GotoImplementationParams, GotoImplementationResponse};
+use lsp_types::{
+    CodeActionParams, CompletionParams, CompletionResponse, DocumentDiagnosticParams,
+    DocumentDiagnosticReport, DocumentDiagnosticReportResult, DocumentHighlight,
+    DocumentHighlightParams, DocumentLink, DocumentLinkParams, DocumentSymbolParams,
+    DocumentSymbolResponse, FullDocumentDiagnosticReport, GotoDefinitionParams,
+    GotoDefinitionResponse, HoverParams, Location, PrepareRenameResponse, ReferenceParams,
+    RelatedFullDocumentDiagnosticReport, RelatedUnchangedDocumentDiagnosticReport, RenameParams,
+    SemanticTokens, SemanticTokensDeltaParams, SemanticTokensFullDeltaResult, SemanticTokensParams,
+    SemanticTokensRangeParams, SemanticTokensRangeResult, SemanticTokensResult,
+    TextDocumentPositionParams, TextEdit, UnchangedDocumentDiagnosticReport, WorkspaceEdit,
+};
+use rust_analyzer_syntax::{AstNode, Edition, SyntaxKind, SyntaxToken, TextRange, ast};
 use snafu::{OptionExt, ResultExt, Whatever};
 
-use crate::code_action;
-use crate::database::{Database, FileUrl, ItemPath, ModulePath};
-use crate::utils::line_col;
+use crate::database::doc_links::{file_doc_links, location_url, resolve_doc_link};
+use crate::database::file::{diagnostic_result_id, file_diagnostics};
+use crate::database::references::find_references;
+use crate::database::resolve::{
+    PathAtPosition, find_by_name, find_impls, module_at, path_at_position, resolve_at,
+};
+use crate::database::scope::{find_block_item, find_local, find_local_uses};
+use crate::database::{Database, FileUrl};
+use crate::document_symbol::document_symbols;
+use crate::semantic_tokens::{semantic_tokens, tokens_delta};
+use crate::utils::{PositionIndex, range};
+use crate::{code_action, completion, hover};
+
+/// Renaming items would need every use across the workspace to resolve exactly, so only names
+/// scoped to a single item are supported
+const RENAME_ERROR: &str =
+    "Only local variables, generic parameters, lifetimes and labels can be renamed";
 
 pub fn code_action(db: &mut Database, request: Request) -> Result<(), Whatever> {
     let params = serde_json::from_value::<CodeActionParams>(request.params)
@@ -14,6 +41,12 @@ pub fn code_action(db: &mut Database, request: Request) -> Result<(), Whatever>
     let mut actions = vec![];
     code_action::add_mod_to_parent(db, &params, &mut actions)
         .whatever_context("failed to add `mod` import to parent file")?;
+    code_action::import_name(db, &params, &mut actions)
+        .whatever_context("failed to add import actions")?;
+    code_action::import_all(db, &params, &mut actions)
+        .whatever_context("failed to add import all action")?;
+    code_action::create_module_file(db, &params, &mut actions)
+        .whatever_context("failed to add create module file actions")?;
 
     let value = serde_json::to_value(actions).expect("failed to turn CodeAction vec to json value");
 
@@ -33,44 +66,39 @@ pub fn definition(db: &mut Database, request: Request) -> Result<(), Whatever> {
     let params = serde_json::from_value::<GotoDefinitionParams>(request.params)
         .whatever_context("received invalid textDocument/definition params")?;
 
+    let uri = params.text_document_position_params.text_document.uri;
     let text_doc = db
-        .get_file(&FileUrl::from_url(
-            params.text_document_position_params.text_document.uri,
-        )?)
+        .get_file(&FileUrl::from_url(uri.clone())?)
         .whatever_context("failed to get definition request file")?;
-    let offset = text_doc
-        .index
-        .offset(line_col(params.text_document_position_params.position))
-        .whatever_context("failed to get position in definition request")?;
-    let range = TextRange::at(offset, TextSize::default());
-    let target_node = text_doc.ast.syntax_node().covering_element(range);
-
-    let name = target_node
-        .as_token()
-        .expect("result of covering element was not a token")
-        .text();
-
-    let path = ItemPath {
-        module: ModulePath {
-            crate_: "crate".to_string(),
-            segments: vec![],
-        },
-        name: name.to_string(),
+    let path = path_at_position(text_doc, params.text_document_position_params.position)
+        .whatever_context("failed to find path in definition request")?;
+
+    // Locals shadow everything else, and anything that can't be resolved is guessed at by name
+    let binding = find_local(&path.token).or_else(|| find_block_item(&path.token));
+    let mut locations = if let Some(binding) = binding {
+        vec![Location::new(
+            uri,
+            range(binding.text_range(), &text_doc.index),
+        )]
+    } else {
+        resolve_at(db, &path)
+            .map(|resolution| resolution.locations(db))
+            .unwrap_or_default()
     };
+    if locations.is_empty()
+        && let Some(name) = path.segments.last()
+    {
+        locations = find_by_name(db, &path.module, name);
+    }
 
-    let result = db
-        .type_defs
-        .get(&path)
-        .map(|data| lsp_types::Location::new(data.file_path.url().clone(), data.range))
-        .map(|loc| serde_json::to_value(loc).expect("failed to turn location into json value"))
-        .or_else(|| {
-            db.function_defs
-                .get(&path)
-                .map(|data| lsp_types::Location::new(data.file_path.url().clone(), data.range))
-                .map(|loc| {
-                    serde_json::to_value(loc).expect("failed to turn location into json value")
-                })
-        });
+    let result = match locations.len() {
+        0 => None,
+        1 => Some(GotoDefinitionResponse::Scalar(locations.remove(0))),
+        _ => Some(GotoDefinitionResponse::Array(locations)),
+    }
+    .map(|response| {
+        serde_json::to_value(response).expect("failed to turn locations into json value")
+    });
 
     let error = if result.is_none() {
         Some(ResponseError {
@@ -93,3 +121,548 @@ pub fn definition(db: &mut Database, request: Request) -> Result<(), Whatever> {
 
     Ok(())
 }
+
+pub fn diagnostic(db: &mut Database, request: Request) -> Result<(), Whatever> {
+    let params = serde_json::from_value::<DocumentDiagnosticParams>(request.params)
+        .whatever_context("received invalid textDocument/diagnostic params")?;
+
+    let file_url = FileUrl::from_url(params.text_document.uri)?;
+    let file = db
+        .get_file(&file_url)
+        .whatever_context("failed to get diagnostic request file")?;
+    let result_id = diagnostic_result_id(file);
+
+    let report = if params.previous_result_id.as_ref() == Some(&result_id) {
+        DocumentDiagnosticReport::Unchanged(RelatedUnchangedDocumentDiagnosticReport {
+            related_documents: None,
+            unchanged_document_diagnostic_report: UnchangedDocumentDiagnosticReport { result_id },
+        })
+    } else {
+        DocumentDiagnosticReport::Full(RelatedFullDocumentDiagnosticReport {
+            related_documents: None,
+            full_document_diagnostic_report: FullDocumentDiagnosticReport {
+                result_id: Some(result_id),
+                items: file_diagnostics(&file_url, file),
+            },
+        })
+    };
+
+    let value = serde_json::to_value(DocumentDiagnosticReportResult::Report(report))
+        .expect("failed to turn diagnostic report to json value");
+
+    db.connection
+        .sender
+        .send(Message::Response(Response {
+            id: request.id,
+            result: Some(value),
+            error: None,
+        }))
+        .whatever_context("failed to send diagnostic response")?;
+
+    Ok(())
+}
+
+pub fn hover(db: &mut Database, request: Request) -> Result<(), Whatever> {
+    let params = serde_json::from_value::<HoverParams>(request.params)
+        .whatever_context("received invalid textDocument/hover params")?;
+
+    let hover = hover::hover(db, &params).whatever_context("failed to build hover")?;
+    let value = serde_json::to_value(hover).expect("failed to turn hover into json value");
+
+    db.connection
+        .sender
+        .send(Message::Response(Response {
+            id: request.id,
+            result: Some(value),
+            error: None,
+        }))
+        .whatever_context("failed to send hover response")?;
+
+    Ok(())
+}
+
+pub fn completion(db: &mut Database, request: Request) -> Result<(), Whatever> {
+    let params = serde_json::from_value::<CompletionParams>(request.params)
+        .whatever_context("received invalid textDocument/completion params")?;
+
+    let list =
+        completion::completions(db, &params).whatever_context("failed to build completions")?;
+    let value = serde_json::to_value(CompletionResponse::List(list))
+        .expect("failed to turn completions into json value");
+
+    db.connection
+        .sender
+        .send(Message::Response(Response {
+            id: request.id,
+            result: Some(value),
+            error: None,
+        }))
+        .whatever_context("failed to send completion response")?;
+
+    Ok(())
+}
+
+pub fn implementation(db: &mut Database, request: Request) -> Result<(), Whatever> {
+    let params = serde_json::from_value::<GotoImplementationParams>(request.params)
+        .whatever_context("received invalid textDocument/implementation params")?;
+
+    let text_doc = db
+        .get_file(&FileUrl::from_url(
+            params.text_document_position_params.text_document.uri,
+        )?)
+        .whatever_context("failed to get implementation request file")?;
+    let PathAtPosition {
+        module, segments, ..
+    } = path_at_position(text_doc, params.text_document_position_params.position)
+        .whatever_context("failed to find path in implementation request")?;
+
+    let mut locations = find_impls(db, &module, &segments);
+
+    // No implementations is an empty answer rather than a failure
+    let response = match locations.len() {
+        1 => GotoImplementationResponse::Scalar(locations.remove(0)),
+        _ => GotoImplementationResponse::Array(locations),
+    };
+
+    db.connection
+        .sender
+        .send(Message::Response(Response::new_ok(request.id, response)))
+        .whatever_context("failed to send implementation response")?;
+
+    Ok(())
+}
+
+pub fn references(db: &mut Database, request: Request) -> Result<(), Whatever> {
+    let params = serde_json::from_value::<ReferenceParams>(request.params)
+        .whatever_context("received invalid textDocument/references params")?;
+
+    let uri = params.text_document_position.text_document.uri;
+    let text_doc = db
+        .get_file(&FileUrl::from_url(uri.clone())?)
+        .whatever_context("failed to get references request file")?;
+    let path = path_at_position(text_doc, params.text_document_position.position)
+        .whatever_context("failed to find path in references request")?;
+    let name = path
+        .segments
+        .last()
+        .whatever_context("references request had an empty path")?;
+
+    if let Some(binding) = find_local(&path.token) {
+        let locations = find_local_uses(&text_doc.ast.syntax_node(), &binding)
+            .into_iter()
+            .filter(|token| params.context.include_declaration || token != &binding)
+            .map(|token| Location::new(uri.clone(), range(token.text_range(), &text_doc.index)))
+            .collect::<Vec<_>>();
+        let value =
+            serde_json::to_value(locations).expect("failed to turn locations into json value");
+
+        db.connection
+            .sender
+            .send(Message::Response(Response {
+                id: request.id,
+                result: Some(value),
+                error: None,
+            }))
+            .whatever_context("failed to send references response")?;
+
+        return Ok(());
+    }
+
+    let target = resolve_at(db, &path);
+    let mut locations = find_references(db, target.as_ref(), name);
+    if params.context.include_declaration
+        && let Some(target) = &target
+    {
+        locations.extend(target.name_locations(db));
+    }
+
+    let value = serde_json::to_value(locations).expect("failed to turn locations into json value");
+
+    db.connection
+        .sender
+        .send(Message::Response(Response {
+            id: request.id,
+            result: Some(value),
+            error: None,
+        }))
+        .whatever_context("failed to send references response")?;
+
+    Ok(())
+}
+
+pub fn document_link(db: &mut Database, request: Request) -> Result<(), Whatever> {
+    let params = serde_json::from_value::<DocumentLinkParams>(request.params)
+        .whatever_context("received invalid textDocument/documentLink params")?;
+
+    let file = FileUrl::from_url(params.text_document.uri)?;
+    db.load_file(&file)
+        .with_whatever_context(|_| format!("failed to load file `{}`", file.url()))?;
+    let text_doc = db
+        .files
+        .get(&file)
+        .expect("successfully loaded file but it was not present in database");
+
+    let links = file_doc_links(&text_doc.ast.syntax_node())
+        .into_iter()
+        .filter_map(|(token, link_range, path)| {
+            let module = module_at(&text_doc.module_path, &token.parent()?);
+            let location = resolve_doc_link(db, &module, &path)?;
+            Some(DocumentLink {
+                range: range(link_range, &text_doc.index),
+                target: Some(location_url(&location)),
+                tooltip: Some(path.join("::")),
+                data: None,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let value = serde_json::to_value(links).expect("failed to turn document links into json value");
+
+    db.connection
+        .sender
+        .send(Message::Response(Response {
+            id: request.id,
+            result: Some(value),
+            error: None,
+        }))
+        .whatever_context("failed to send documentLink response")?;
+
+    Ok(())
+}
+
+pub fn document_symbol(db: &mut Database, request: Request) -> Result<(), Whatever> {
+    let params = serde_json::from_value::<DocumentSymbolParams>(request.params)
+        .whatever_context("received invalid textDocument/documentSymbol params")?;
+
+    let text_doc = db
+        .get_file(&FileUrl::from_url(params.text_document.uri)?)
+        .whatever_context("failed to get documentSymbol request file")?;
+    let symbols = DocumentSymbolResponse::Nested(document_symbols(text_doc));
+
+    let value = serde_json::to_value(symbols).expect("failed to turn symbols into json value");
+
+    db.connection
+        .sender
+        .send(Message::Response(Response {
+            id: request.id,
+            result: Some(value),
+            error: None,
+        }))
+        .whatever_context("failed to send documentSymbol response")?;
+
+    Ok(())
+}
+
+pub fn document_highlight(db: &mut Database, request: Request) -> Result<(), Whatever> {
+    let params = serde_json::from_value::<DocumentHighlightParams>(request.params)
+        .whatever_context("received invalid textDocument/documentHighlight params")?;
+
+    let uri = params.text_document_position_params.text_document.uri;
+    let text_doc = db
+        .get_file(&FileUrl::from_url(uri.clone())?)
+        .whatever_context("failed to get documentHighlight request file")?;
+    let path = path_at_position(text_doc, params.text_document_position_params.position)
+        .whatever_context("failed to find path in documentHighlight request")?;
+
+    let mut ranges = vec![];
+    if let Some(binding) = find_local(&path.token) {
+        ranges.extend(
+            find_local_uses(&text_doc.ast.syntax_node(), &binding)
+                .into_iter()
+                .map(|token| range(token.text_range(), &text_doc.index)),
+        );
+    } else if let Some(name) = path.segments.last() {
+        ranges.push(range(path.token.text_range(), &text_doc.index));
+        let target = resolve_at(db, &path);
+        let mut locations = find_references(db, target.as_ref(), name);
+        if let Some(target) = &target {
+            locations.extend(target.name_locations(db));
+        }
+        ranges.extend(
+            locations
+                .into_iter()
+                .filter(|location| location.uri == uri)
+                .map(|location| location.range),
+        );
+        ranges.sort_by_key(|range| (range.start.line, range.start.character));
+        ranges.dedup();
+    }
+
+    let highlights = ranges
+        .into_iter()
+        .map(|range| DocumentHighlight { range, kind: None })
+        .collect::<Vec<_>>();
+    let value =
+        serde_json::to_value(highlights).expect("failed to turn highlights into json value");
+
+    db.connection
+        .sender
+        .send(Message::Response(Response {
+            id: request.id,
+            result: Some(value),
+            error: None,
+        }))
+        .whatever_context("failed to send documentHighlight response")?;
+
+    Ok(())
+}
+
+pub fn prepare_rename(db: &mut Database, request: Request) -> Result<(), Whatever> {
+    let params = serde_json::from_value::<TextDocumentPositionParams>(request.params)
+        .whatever_context("received invalid textDocument/prepareRename params")?;
+
+    let text_doc = db
+        .get_file(&FileUrl::from_url(params.text_document.uri)?)
+        .whatever_context("failed to get prepareRename request file")?;
+    let path = path_at_position(text_doc, params.position)
+        .whatever_context("failed to find path in prepareRename request")?;
+
+    let (result, error) = if find_local(&path.token).is_some() {
+        let response =
+            PrepareRenameResponse::Range(range(path.token.text_range(), &text_doc.index));
+        let value =
+            serde_json::to_value(response).expect("failed to turn rename range into json value");
+        (Some(value), None)
+    } else {
+        let error = ResponseError {
+            code: ErrorCode::RequestFailed as i32,
+            message: RENAME_ERROR.to_string(),
+            data: None,
+        };
+        (None, Some(error))
+    };
+
+    db.connection
+        .sender
+        .send(Message::Response(Response {
+            id: request.id,
+            result,
+            error,
+        }))
+        .whatever_context("failed to send prepareRename response")?;
+
+    Ok(())
+}
+
+pub fn rename(db: &mut Database, request: Request) -> Result<(), Whatever> {
+    let params = serde_json::from_value::<RenameParams>(request.params)
+        .whatever_context("received invalid textDocument/rename params")?;
+
+    let uri = params.text_document_position.text_document.uri;
+    let text_doc = db
+        .get_file(&FileUrl::from_url(uri.clone())?)
+        .whatever_context("failed to get rename request file")?;
+    let path = path_at_position(text_doc, params.text_document_position.position)
+        .whatever_context("failed to find path in rename request")?;
+
+    let (result, error) = if let Some(binding) = find_local(&path.token) {
+        // Lifetimes and labels keep their leading `'` even if the new name left it out
+        let mut new_name = params.new_name;
+        if binding.kind() == SyntaxKind::LIFETIME_IDENT && !new_name.starts_with('\'') {
+            new_name.insert(0, '\'');
+        }
+
+        if is_valid_name(&new_name, binding.kind(), text_doc.edition) {
+            let edits = find_local_uses(&text_doc.ast.syntax_node(), &binding)
+                .into_iter()
+                .map(|token| rename_edit(&token, &new_name, &text_doc.index))
+                .collect();
+            let edit = WorkspaceEdit::new(HashMap::from([(uri, edits)]));
+            let value =
+                serde_json::to_value(edit).expect("failed to turn workspace edit into json value");
+            (Some(value), None)
+        } else {
+            let error = ResponseError {
+                code: ErrorCode::InvalidParams as i32,
+                message: format!("`{new_name}` is not a valid name"),
+                data: None,
+            };
+            (None, Some(error))
+        }
+    } else {
+        let error = ResponseError {
+            code: ErrorCode::RequestFailed as i32,
+            message: RENAME_ERROR.to_string(),
+            data: None,
+        };
+        (None, Some(error))
+    };
+
+    db.connection
+        .sender
+        .send(Message::Response(Response {
+            id: request.id,
+            result,
+            error,
+        }))
+        .whatever_context("failed to send rename response")?;
+
+    Ok(())
+}
+
+/// Whether `name` can replace a binding of the given kind: an identifier that isn't a keyword, or
+/// a lifetime named by one
+fn is_valid_name(name: &str, kind: SyntaxKind, edition: Edition) -> bool {
+    let is_identifier = |name: &str| {
+        let mut chars = name.chars();
+        name != "_"
+            && chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+            && chars.all(|c| c.is_alphanumeric() || c == '_')
+    };
+    let is_keyword = |name: &str| SyntaxKind::from_keyword(name, edition).is_some();
+
+    if kind == SyntaxKind::LIFETIME_IDENT {
+        return name
+            .strip_prefix('\'')
+            .is_some_and(|name| is_identifier(name) && !is_keyword(name));
+    }
+    match name.strip_prefix("r#") {
+        Some(raw) => is_identifier(raw) && !matches!(raw, "crate" | "self" | "Self" | "super"),
+        None => is_identifier(name) && !is_keyword(name),
+    }
+}
+
+/// Rename a single use of a local. Shorthand fields like `Foo { x }` are expanded to
+/// `Foo { x: y }`, so that they keep referring to the same field.
+fn rename_edit(token: &SyntaxToken, new_name: &str, index: &PositionIndex) -> TextEdit {
+    let field = token.text();
+    let is_shorthand_expr = token
+        .parent_ancestors()
+        .find_map(ast::PathExpr::cast)
+        .and_then(|expr| expr.syntax().parent())
+        .and_then(ast::RecordExprField::cast)
+        .is_some_and(|field| field.colon_token().is_none());
+    if is_shorthand_expr {
+        return TextEdit::new(
+            range(token.text_range(), index),
+            format!("{field}: {new_name}"),
+        );
+    }
+
+    // `Foo { ref mut x }` keeps its modifiers after the field name
+    let shorthand_pat = token
+        .parent()
+        .and_then(|name| name.parent())
+        .and_then(ast::IdentPat::cast)
+        .filter(|pat| {
+            pat.syntax()
+                .parent()
+                .and_then(ast::RecordPatField::cast)
+                .is_some_and(|field| field.colon_token().is_none())
+        });
+    if let Some(pat) = shorthand_pat {
+        let start = pat.syntax().text_range().start();
+        let modifiers = TextRange::new(start, token.text_range().start()) - start;
+        let modifiers = &pat.syntax().text().to_string()[modifiers];
+        return TextEdit::new(
+            range(TextRange::new(start, token.text_range().end()), index),
+            format!("{field}: {modifiers}{new_name}"),
+        );
+    }
+
+    TextEdit::new(range(token.text_range(), index), new_name.to_string())
+}
+
+pub fn semantic_tokens_full(db: &mut Database, request: Request) -> Result<(), Whatever> {
+    let params = serde_json::from_value::<SemanticTokensParams>(request.params)
+        .whatever_context("received invalid textDocument/semanticTokens/full params")?;
+
+    let file = FileUrl::from_url(params.text_document.uri)?;
+    let tokens = file_semantic_tokens(db, &file)?;
+    let value = serde_json::to_value(SemanticTokensResult::Tokens(tokens))
+        .expect("failed to turn semantic tokens into json value");
+
+    db.connection
+        .sender
+        .send(Message::Response(Response {
+            id: request.id,
+            result: Some(value),
+            error: None,
+        }))
+        .whatever_context("failed to send semanticTokens/full response")?;
+
+    Ok(())
+}
+
+pub fn semantic_tokens_full_delta(db: &mut Database, request: Request) -> Result<(), Whatever> {
+    let params = serde_json::from_value::<SemanticTokensDeltaParams>(request.params)
+        .whatever_context("received invalid textDocument/semanticTokens/full/delta params")?;
+
+    let file = FileUrl::from_url(params.text_document.uri)?;
+    let previous = db
+        .files
+        .get_mut(&file)
+        .and_then(|file| file.semantic_tokens.take())
+        .filter(|previous| previous.result_id.as_ref() == Some(&params.previous_result_id));
+    let tokens = file_semantic_tokens(db, &file)?;
+
+    let result = match previous {
+        Some(previous) => {
+            SemanticTokensFullDeltaResult::TokensDelta(tokens_delta(&previous, &tokens))
+        }
+        None => SemanticTokensFullDeltaResult::Tokens(tokens),
+    };
+    let value =
+        serde_json::to_value(result).expect("failed to turn semantic tokens into json value");
+
+    db.connection
+        .sender
+        .send(Message::Response(Response {
+            id: request.id,
+            result: Some(value),
+            error: None,
+        }))
+        .whatever_context("failed to send semanticTokens/full/delta response")?;
+
+    Ok(())
+}
+
+pub fn semantic_tokens_range(db: &mut Database, request: Request) -> Result<(), Whatever> {
+    let params = serde_json::from_value::<SemanticTokensRangeParams>(request.params)
+        .whatever_context("received invalid textDocument/semanticTokens/range params")?;
+
+    let file = FileUrl::from_url(params.text_document.uri)?;
+    db.load_file(&file)
+        .with_whatever_context(|_| format!("failed to load file `{}`", file.url()))?;
+    let text_doc = db
+        .files
+        .get(&file)
+        .expect("successfully loaded file but it was not present in database");
+    let range = text_doc
+        .index
+        .text_range(params.range)
+        .whatever_context("semantic tokens range was outside of the file")?;
+
+    // Range results are partial, so they aren't kept around for deltas
+    let tokens = semantic_tokens(db, text_doc, Some(range));
+    let value = serde_json::to_value(SemanticTokensRangeResult::Tokens(tokens))
+        .expect("failed to turn semantic tokens into json value");
+
+    db.connection
+        .sender
+        .send(Message::Response(Response {
+            id: request.id,
+            result: Some(value),
+            error: None,
+        }))
+        .whatever_context("failed to send semanticTokens/range response")?;
+
+    Ok(())
+}
+
+/// Compute the semantic tokens of the whole file, remembering them to compute later deltas against
+fn file_semantic_tokens(db: &mut Database, file: &FileUrl) -> Result<SemanticTokens, Whatever> {
+    db.load_file(file)
+        .with_whatever_context(|_| format!("failed to load file `{}`", file.url()))?;
+    let text_doc = db
+        .files
+        .get(file)
+        .expect("successfully loaded file but it was not present in database");
+    let tokens = semantic_tokens(db, text_doc, None);
+
+    db.files
+        .get_mut(file)
+        .expect("successfully loaded file but it was not present in database")
+        .semantic_tokens = Some(tokens.clone());
+    Ok(tokens)
+}
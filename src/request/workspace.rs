@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+
+use lsp_server::{Message, Request, Response};
+use lsp_types::{
+    FullDocumentDiagnosticReport, SymbolInformation, UnchangedDocumentDiagnosticReport,
+    WorkspaceDiagnosticParams, WorkspaceDiagnosticReport, WorkspaceDiagnosticReportResult,
+    WorkspaceDocumentDiagnosticReport, WorkspaceFullDocumentDiagnosticReport,
+    WorkspaceSymbolParams, WorkspaceSymbolResponse, WorkspaceUnchangedDocumentDiagnosticReport,
+};
+use snafu::{ResultExt, Whatever};
+
+use crate::database::Database;
+use crate::database::file::{diagnostic_result_id, file_diagnostics};
+
+pub fn diagnostic(db: &mut Database, request: Request) -> Result<(), Whatever> {
+    let params = serde_json::from_value::<WorkspaceDiagnosticParams>(request.params)
+        .whatever_context("received invalid workspace/diagnostic params")?;
+
+    let previous_result_ids = params
+        .previous_result_ids
+        .into_iter()
+        .map(|previous| (previous.uri, previous.value))
+        .collect::<HashMap<_, _>>();
+
+    let items =
+        db.files
+            .iter()
+            .map(|(file, data)| {
+                let uri = file.url().clone();
+                let version = Some(data.version as i64);
+                let result_id = diagnostic_result_id(data);
+
+                if previous_result_ids.get(&uri) == Some(&result_id) {
+                    WorkspaceDocumentDiagnosticReport::Unchanged(
+                        WorkspaceUnchangedDocumentDiagnosticReport {
+                            uri,
+                            version,
+                            unchanged_document_diagnostic_report:
+                                UnchangedDocumentDiagnosticReport { result_id },
+                        },
+                    )
+                } else {
+                    WorkspaceDocumentDiagnosticReport::Full(WorkspaceFullDocumentDiagnosticReport {
+                        uri,
+                        version,
+                        full_document_diagnostic_report: FullDocumentDiagnosticReport {
+                            result_id: Some(result_id),
+                            items: file_diagnostics(file, data),
+                        },
+                    })
+                }
+            })
+            .collect();
+
+    let value = serde_json::to_value(WorkspaceDiagnosticReportResult::Report(
+        WorkspaceDiagnosticReport { items },
+    ))
+    .expect("failed to turn workspace diagnostic report to json value");
+
+    db.connection
+        .sender
+        .send(Message::Response(Response {
+            id: request.id,
+            result: Some(value),
+            error: None,
+        }))
+        .whatever_context("failed to send workspace diagnostic response")?;
+
+    Ok(())
+}
+
+pub fn symbol(db: &mut Database, request: Request) -> Result<(), Whatever> {
+    let params = serde_json::from_value::<WorkspaceSymbolParams>(request.params)
+        .whatever_context("received invalid workspace/symbol params")?;
+
+    #[allow(deprecated)]
+    let symbols = db
+        .symbols
+        .search(&params.query)
+        .into_iter()
+        .map(|symbol| SymbolInformation {
+            name: symbol.name.clone(),
+            kind: symbol.kind,
+            tags: None,
+            deprecated: None,
+            location: symbol.location.clone(),
+            container_name: Some(symbol.container.join("::")),
+        })
+        .collect::<Vec<_>>();
+
+    let value = serde_json::to_value(WorkspaceSymbolResponse::Flat(symbols))
+        .expect("failed to turn symbols into json value");
+
+    db.connection
+        .sender
+        .send(Message::Response(Response {
+            id: request.id,
+            result: Some(value),
+            error: None,
+        }))
+        .whatever_context("failed to send workspace symbol response")?;
+
+    Ok(())
+}
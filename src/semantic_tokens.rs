@@ -0,0 +1,525 @@
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use lsp_types::{
+    SemanticToken, SemanticTokenModifier, SemanticTokenType, SemanticTokens, SemanticTokensDelta,
+    SemanticTokensEdit, SemanticTokensLegend,
+};
+use rust_analyzer_syntax::ast::{self, HasVisibility};
+use rust_analyzer_syntax::{AstNode, SyntaxKind, SyntaxNode, SyntaxToken, TextRange};
+
+use crate::database::doc_links::file_doc_links;
+use crate::database::resolve::{
+    Resolution, find_associated, module_at, path_at_token, resolve_path,
+};
+use crate::database::scope::{find_block_item, find_local, is_binding};
+use crate::database::{ChildKind, Database, FileData, FileUrl, ItemKind, ModulePath};
+use crate::hover::node_at_range;
+
+/// Token types, in the order of [`TOKEN_TYPES`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TokenType {
+    Namespace,
+    Type,
+    Struct,
+    Enum,
+    Interface,
+    TypeParameter,
+    Parameter,
+    Variable,
+    Property,
+    EnumMember,
+    Function,
+    Method,
+    Macro,
+    Lifetime,
+}
+
+const TOKEN_TYPES: [SemanticTokenType; 14] = [
+    SemanticTokenType::NAMESPACE,
+    SemanticTokenType::TYPE,
+    SemanticTokenType::STRUCT,
+    SemanticTokenType::ENUM,
+    SemanticTokenType::INTERFACE,
+    SemanticTokenType::TYPE_PARAMETER,
+    SemanticTokenType::PARAMETER,
+    SemanticTokenType::VARIABLE,
+    SemanticTokenType::PROPERTY,
+    SemanticTokenType::ENUM_MEMBER,
+    SemanticTokenType::FUNCTION,
+    SemanticTokenType::METHOD,
+    SemanticTokenType::MACRO,
+    SemanticTokenType::new("lifetime"),
+];
+
+/// Token modifiers, as bits in the order of [`TOKEN_MODIFIERS`]
+const DECLARATION: u32 = 1 << 0;
+const MUTABLE: u32 = 1 << 1;
+const UNSAFE: u32 = 1 << 2;
+const PUBLIC: u32 = 1 << 3;
+const DOCUMENTATION: u32 = 1 << 4;
+
+const TOKEN_MODIFIERS: [SemanticTokenModifier; 5] = [
+    SemanticTokenModifier::DECLARATION,
+    SemanticTokenModifier::new("mutable"),
+    SemanticTokenModifier::new("unsafe"),
+    SemanticTokenModifier::new("public"),
+    SemanticTokenModifier::DOCUMENTATION,
+];
+
+pub fn legend() -> SemanticTokensLegend {
+    SemanticTokensLegend {
+        token_types: TOKEN_TYPES.to_vec(),
+        token_modifiers: TOKEN_MODIFIERS.to_vec(),
+    }
+}
+
+/// Classify every identifier and lifetime in `file` (or just those overlapping `range`), along
+/// with intra-doc links
+pub fn semantic_tokens(db: &Database, file: &FileData, range: Option<TextRange>) -> SemanticTokens {
+    let root = file.ast.syntax_node();
+    let mut tokens = vec![];
+    let mut resolved = Resolved::new();
+
+    for token in root
+        .descendants_with_tokens()
+        .filter_map(|element| element.into_token())
+        .filter(|token| range.is_none_or(|range| range.intersect(token.text_range()).is_some()))
+    {
+        let classified = match token.kind() {
+            SyntaxKind::IDENT => classify_ident(db, file, &mut resolved, &token),
+            SyntaxKind::LIFETIME_IDENT => Some(classify_lifetime(&token)),
+            _ => None,
+        };
+        if let Some((token_type, modifiers)) = classified {
+            tokens.push((token.text_range(), token_type, modifiers));
+        }
+    }
+
+    for (token, link_range, path) in file_doc_links(&root) {
+        if range.is_some_and(|range| range.intersect(link_range).is_none()) {
+            continue;
+        }
+
+        let Some(parent) = token.parent() else {
+            continue;
+        };
+        let module = module_at(&file.module_path, &parent);
+        if let Some((token_type, modifiers)) = classify_path(db, &mut resolved, module, path) {
+            tokens.push((link_range, token_type, modifiers | DOCUMENTATION));
+        }
+    }
+
+    tokens.sort_by_key(|(range, _, _)| range.start());
+    let data = encode(file, &tokens);
+    SemanticTokens {
+        result_id: Some(result_id(&data)),
+        data,
+    }
+}
+
+/// The edits turning `previous` into `current`, as a single edit replacing everything between
+/// their common prefix and suffix
+pub fn tokens_delta(previous: &SemanticTokens, current: &SemanticTokens) -> SemanticTokensDelta {
+    let (old, new) = (&previous.data, &current.data);
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let edits = if prefix == old.len() && prefix == new.len() {
+        vec![]
+    } else {
+        // Edit offsets count the integers in the encoded data, of which there are five per token
+        vec![SemanticTokensEdit {
+            start: (prefix * 5) as u32,
+            delete_count: ((old.len() - prefix - suffix) * 5) as u32,
+            data: Some(new[prefix..new.len() - suffix].to_vec()),
+        }]
+    };
+
+    SemanticTokensDelta {
+        result_id: current.result_id.clone(),
+        edits,
+    }
+}
+
+/// Turn sorted tokens into the relative positions LSP expects. Tokens can't span lines, so
+/// multi-line ones (which identifiers never are) are dropped.
+fn encode(file: &FileData, tokens: &[(TextRange, TokenType, u32)]) -> Vec<SemanticToken> {
+    let mut data = vec![];
+    let mut previous = lsp_types::Position::new(0, 0);
+
+    for (range, token_type, modifiers) in tokens {
+        let start = file.index.position(range.start());
+        let end = file.index.position(range.end());
+        if start.line != end.line {
+            continue;
+        }
+
+        let delta_line = start.line - previous.line;
+        let delta_start = match delta_line {
+            0 => start.character - previous.character,
+            _ => start.character,
+        };
+        data.push(SemanticToken {
+            delta_line,
+            delta_start,
+            length: end.character - start.character,
+            token_type: *token_type as u32,
+            token_modifiers_bitset: *modifiers,
+        });
+        previous = start;
+    }
+
+    data
+}
+
+fn result_id(data: &[SemanticToken]) -> String {
+    let mut hasher = DefaultHasher::new();
+    for token in data {
+        (
+            token.delta_line,
+            token.delta_start,
+            token.length,
+            token.token_type,
+            token.token_modifiers_bitset,
+        )
+            .hash(&mut hasher);
+    }
+    format!("{:x}", hasher.finish())
+}
+
+/// How the paths already resolved in a file were classified, by the module they're written in.
+/// The same names come up over and over, and resolving one can mean following many imports.
+type Resolved = HashMap<(ModulePath, Vec<String>), Option<(TokenType, u32)>>;
+
+fn classify_path(
+    db: &Database,
+    resolved: &mut Resolved,
+    module: ModulePath,
+    segments: Vec<String>,
+) -> Option<(TokenType, u32)> {
+    *resolved
+        .entry((module, segments))
+        .or_insert_with_key(|(module, segments)| {
+            resolve_path(db, module, segments)
+                .and_then(|resolution| classify_resolution(db, &resolution))
+        })
+}
+
+/// Locals are classified from the syntax tree alone; only paths that aren't locals get resolved
+fn classify_ident(
+    db: &Database,
+    file: &FileData,
+    resolved: &mut Resolved,
+    token: &SyntaxToken,
+) -> Option<(TokenType, u32)> {
+    let parent = token.parent()?;
+    if let Some(name) = ast::Name::cast(parent.clone()) {
+        // `None` in a pattern names a variant rather than declaring a binding
+        let names_item = name
+            .syntax()
+            .parent()
+            .and_then(ast::IdentPat::cast)
+            .is_some_and(|ident| !is_binding(&ident));
+        if !names_item {
+            return classify_declaration(&name);
+        }
+
+        let module = module_at(&file.module_path, &parent);
+        return classify_path(db, resolved, module, vec![token.text().to_string()]);
+    }
+
+    if let Some(binding) = find_local(token).or_else(|| find_block_item(token)) {
+        let name = binding.parent().and_then(ast::Name::cast)?;
+        let (token_type, modifiers) = classify_declaration(&name)?;
+        return Some((token_type, modifiers & !DECLARATION));
+    }
+
+    if parent.kind() != SyntaxKind::NAME_REF {
+        return None;
+    }
+
+    let module = module_at(&file.module_path, &parent);
+    path_at_token(token)
+        .and_then(|segments| classify_path(db, resolved, module, segments))
+        .or_else(|| classify_unresolved(&parent))
+}
+
+fn classify_lifetime(token: &SyntaxToken) -> (TokenType, u32) {
+    let is_declaration = token
+        .parent()
+        .and_then(|lifetime| lifetime.parent())
+        .is_some_and(|parent| {
+            matches!(
+                parent.kind(),
+                SyntaxKind::LIFETIME_PARAM | SyntaxKind::LABEL
+            )
+        });
+
+    match is_declaration {
+        true => (TokenType::Lifetime, DECLARATION),
+        false => (TokenType::Lifetime, 0),
+    }
+}
+
+fn classify_declaration(name: &ast::Name) -> Option<(TokenType, u32)> {
+    let declaration = name.syntax().parent()?;
+    let mut modifiers = DECLARATION;
+
+    let is_public = ast::AnyHasVisibility::cast(declaration.clone())
+        .and_then(|item| item.visibility())
+        .is_some_and(|visibility| visibility.syntax().text() == "pub");
+    if is_public {
+        modifiers |= PUBLIC;
+    }
+
+    let token_type = match declaration.kind() {
+        SyntaxKind::STRUCT | SyntaxKind::UNION => TokenType::Struct,
+        SyntaxKind::ENUM => TokenType::Enum,
+        SyntaxKind::TRAIT => {
+            if ast::Trait::cast(declaration.clone())?
+                .unsafe_token()
+                .is_some()
+            {
+                modifiers |= UNSAFE;
+            }
+            TokenType::Interface
+        }
+        SyntaxKind::TYPE_ALIAS => TokenType::Type,
+        SyntaxKind::FN => {
+            let function = ast::Fn::cast(declaration.clone())?;
+            if function.unsafe_token().is_some() {
+                modifiers |= UNSAFE;
+            }
+            let has_self = function
+                .param_list()
+                .is_some_and(|params| params.self_param().is_some());
+            match has_self {
+                true => TokenType::Method,
+                false => TokenType::Function,
+            }
+        }
+        SyntaxKind::CONST => TokenType::Variable,
+        SyntaxKind::STATIC => {
+            if ast::Static::cast(declaration.clone())?
+                .mut_token()
+                .is_some()
+            {
+                modifiers |= MUTABLE;
+            }
+            TokenType::Variable
+        }
+        SyntaxKind::MACRO_RULES | SyntaxKind::MACRO_DEF => TokenType::Macro,
+        SyntaxKind::MODULE => TokenType::Namespace,
+        SyntaxKind::VARIANT => TokenType::EnumMember,
+        SyntaxKind::RECORD_FIELD => TokenType::Property,
+        SyntaxKind::TYPE_PARAM | SyntaxKind::CONST_PARAM => TokenType::TypeParameter,
+        SyntaxKind::IDENT_PAT => {
+            let pat = ast::IdentPat::cast(declaration.clone())?;
+            if pat.mut_token().is_some() {
+                modifiers |= MUTABLE;
+            }
+            let is_param = declaration
+                .ancestors()
+                .take_while(|node| !matches!(node.kind(), SyntaxKind::LET_STMT | SyntaxKind::FN))
+                .any(|node| node.kind() == SyntaxKind::PARAM);
+            match is_param {
+                true => TokenType::Parameter,
+                false => TokenType::Variable,
+            }
+        }
+        _ => return None,
+    };
+
+    Some((token_type, modifiers))
+}
+
+fn classify_resolution(db: &Database, resolution: &Resolution) -> Option<(TokenType, u32)> {
+    match resolution {
+        Resolution::Module(_) => Some((TokenType::Namespace, 0)),
+        Resolution::Item(item) => {
+            let data = db.items.get(item)?.first()?;
+            let token_type = match data.kind {
+                ItemKind::Struct | ItemKind::Union => TokenType::Struct,
+                ItemKind::Enum => TokenType::Enum,
+                ItemKind::Trait => TokenType::Interface,
+                ItemKind::TypeAlias => TokenType::Type,
+                ItemKind::Fn => TokenType::Function,
+                ItemKind::Const | ItemKind::Static => TokenType::Variable,
+                ItemKind::Macro => TokenType::Macro,
+                ItemKind::ExternCrate => TokenType::Namespace,
+            };
+            Some((token_type, fn_modifiers(db, &data.file_path, data.range)))
+        }
+        Resolution::Child { owner, name } => {
+            let child = db
+                .items
+                .get(owner)?
+                .iter()
+                .flat_map(|owner| &owner.children)
+                .find(|child| &child.name == name)?;
+            match child.kind {
+                ChildKind::Field => Some((TokenType::Property, 0)),
+                ChildKind::Variant => Some((TokenType::EnumMember, 0)),
+            }
+        }
+        Resolution::Associated { owner, name } => {
+            let data = find_associated(db, owner, name).next()?;
+            let function = fn_at(db, &data.file_path, data.range);
+            let token_type = match data.kind {
+                // Matching the declaration, associated functions without `self` aren't methods
+                ItemKind::Fn => {
+                    match function.as_ref().and_then(|function| function.param_list()) {
+                        Some(params) if params.self_param().is_some() => TokenType::Method,
+                        _ => TokenType::Function,
+                    }
+                }
+                ItemKind::TypeAlias => TokenType::Type,
+                _ => TokenType::Variable,
+            };
+            Some((token_type, fn_modifiers(db, &data.file_path, data.range)))
+        }
+    }
+}
+
+/// The function defined at `range` in `file`, if the item there is one
+fn fn_at(db: &Database, file: &FileUrl, range: lsp_types::Range) -> Option<ast::Fn> {
+    db.files
+        .get(file)
+        .and_then(|file| node_at_range(file, range))
+        .and_then(ast::Fn::cast)
+}
+
+/// [`UNSAFE`] if the item defined at `range` in `file` is an `unsafe fn`
+fn fn_modifiers(db: &Database, file: &FileUrl, range: lsp_types::Range) -> u32 {
+    match fn_at(db, file, range).is_some_and(|function| function.unsafe_token().is_some()) {
+        true => UNSAFE,
+        false => 0,
+    }
+}
+
+/// Guess at names that couldn't be resolved (usually from other crates) by where they're used
+fn classify_unresolved(name_ref: &SyntaxNode) -> Option<(TokenType, u32)> {
+    let parent = name_ref.parent()?;
+    let token_type = match parent.kind() {
+        SyntaxKind::FIELD_EXPR | SyntaxKind::RECORD_EXPR_FIELD | SyntaxKind::RECORD_PAT_FIELD => {
+            TokenType::Property
+        }
+        SyntaxKind::METHOD_CALL_EXPR => TokenType::Method,
+        SyntaxKind::PATH_SEGMENT => {
+            let path = ast::PathSegment::cast(parent)?.parent_path();
+            let path_parent = path.syntax().parent()?;
+
+            if path_parent.kind() == SyntaxKind::MACRO_CALL {
+                TokenType::Macro
+            } else if path_parent.kind() == SyntaxKind::PATH {
+                // A qualifier, like the `std` in `std::mem`
+                let is_type = name_ref.text().to_string().chars().next()?.is_uppercase();
+                match is_type {
+                    true => TokenType::Type,
+                    false => TokenType::Namespace,
+                }
+            } else if path_parent.kind() == SyntaxKind::PATH_TYPE {
+                TokenType::Type
+            } else if path_parent
+                .parent()
+                .is_some_and(|grandparent| grandparent.kind() == SyntaxKind::CALL_EXPR)
+            {
+                TokenType::Function
+            } else {
+                return None;
+            }
+        }
+        _ => return None,
+    };
+
+    Some((token_type, 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{PositionEncoding, PositionIndex};
+    use line_index::WideEncoding;
+
+    fn token(delta_line: u32, delta_start: u32, length: u32) -> SemanticToken {
+        SemanticToken {
+            delta_line,
+            delta_start,
+            length,
+            token_type: TokenType::Variable as u32,
+            token_modifiers_bitset: 0,
+        }
+    }
+
+    fn tokens(data: Vec<SemanticToken>) -> SemanticTokens {
+        SemanticTokens {
+            result_id: Some(result_id(&data)),
+            data,
+        }
+    }
+
+    #[test]
+    fn encode_uses_relative_positions() {
+        let text = "é a b\nc\n\"d\ne\"";
+        let file = FileData {
+            index: PositionIndex::new(text, PositionEncoding::Wide(WideEncoding::Utf16)),
+            ..FileData::default()
+        };
+        let range = |start: u32, end: u32| TextRange::new(start.into(), end.into());
+        let data = encode(
+            &file,
+            &[
+                (range(3, 4), TokenType::Variable, 0),
+                (range(5, 6), TokenType::Variable, 0),
+                (range(7, 8), TokenType::Variable, 0),
+                // Spans two lines, so it's dropped
+                (range(9, 14), TokenType::Variable, 0),
+            ],
+        );
+        assert_eq!(data, [token(0, 2, 1), token(0, 2, 1), token(1, 0, 1)]);
+    }
+
+    #[test]
+    fn delta_replaces_what_changed_between_prefix_and_suffix() {
+        let previous = tokens(vec![token(0, 1, 1), token(1, 2, 3), token(1, 0, 4)]);
+        let current = tokens(vec![token(0, 1, 1), token(0, 5, 2), token(1, 0, 4)]);
+        let delta = tokens_delta(&previous, &current);
+        assert_eq!(delta.result_id, current.result_id);
+        assert_eq!(
+            delta.edits,
+            [SemanticTokensEdit {
+                start: 5,
+                delete_count: 5,
+                data: Some(vec![token(0, 5, 2)]),
+            }]
+        );
+
+        let inserted = tokens(vec![
+            token(0, 1, 1),
+            token(0, 3, 1),
+            token(1, 2, 3),
+            token(1, 0, 4),
+        ]);
+        let delta = tokens_delta(&previous, &inserted);
+        assert_eq!(
+            delta.edits,
+            [SemanticTokensEdit {
+                start: 5,
+                delete_count: 0,
+                data: Some(vec![token(0, 3, 1)]),
+            }]
+        );
+    }
+
+    #[test]
+    fn delta_is_empty_when_nothing_changed() {
+        let previous = tokens(vec![token(0, 1, 1), token(1, 2, 3)]);
+        assert!(tokens_delta(&previous, &previous).edits.is_empty());
+    }
+}
@@ -0,0 +1,55 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+use lsp_server::Connection;
+use lsp_types::InitializeParams;
+
+use crate::database::Database;
+use crate::database::symbol_index::SymbolIndex;
+use crate::utils::PositionEncoding;
+
+/// A fresh directory containing `files`, removed again when dropped
+pub struct TempDir(pub PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str, files: &[(&str, &str)]) -> TempDir {
+        let dir =
+            std::env::temp_dir().join(format!("rust-navigator-{name}-{}", std::process::id()));
+        _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for (path, contents) in files {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        TempDir(dir.canonicalize().unwrap())
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// An empty database, along with the client's end of its connection, which has to be kept alive
+/// while the database sends anything
+pub fn database() -> (Database, Connection) {
+    let (connection, client) = Connection::memory();
+    let db = Database {
+        connection,
+        initialize_params: InitializeParams::default(),
+        workspace_folders: vec![],
+        position_encoding: PositionEncoding::Utf8,
+        files: HashMap::default(),
+        modules: HashMap::default(),
+        items: HashMap::default(),
+        associated_items: HashMap::default(),
+        name_refs: HashMap::default(),
+        symbols: SymbolIndex::default(),
+        packages: vec![],
+        module_files: HashMap::default(),
+        updating: HashSet::default(),
+    };
+    (db, client)
+}
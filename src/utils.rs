@@ -1,30 +1,128 @@
 use std::fmt::Display;
 
-use line_index::{LineCol, LineIndex, TextRange};
+use line_index::{LineIndex, TextRange, TextSize, WideEncoding, WideLineCol};
+use lsp_types::PositionEncodingKind;
+use rust_analyzer_syntax::{SyntaxKind, SyntaxNode, SyntaxToken};
 
 use crate::database::Database;
 
-pub fn position(point: LineCol) -> lsp_types::Position {
-    lsp_types::Position {
-        line: point.line,
-        character: point.col,
+/// How the client counts the `character` of a position, negotiated on initialization
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PositionEncoding {
+    Utf8,
+    Wide(WideEncoding),
+}
+
+impl PositionEncoding {
+    /// Use UTF-8 when the client supports it, since it needs no conversion; otherwise fall back to
+    /// UTF-16, which every client must support
+    pub fn negotiate(client_encodings: Option<&[PositionEncodingKind]>) -> PositionEncoding {
+        let supports_utf8 = client_encodings
+            .is_some_and(|encodings| encodings.contains(&PositionEncodingKind::UTF8));
+        if supports_utf8 {
+            PositionEncoding::Utf8
+        } else {
+            PositionEncoding::Wide(WideEncoding::Utf16)
+        }
     }
+
+    pub fn kind(self) -> PositionEncodingKind {
+        match self {
+            PositionEncoding::Utf8 => PositionEncodingKind::UTF8,
+            PositionEncoding::Wide(WideEncoding::Utf32) => PositionEncodingKind::UTF32,
+            PositionEncoding::Wide(_) => PositionEncodingKind::UTF16,
+        }
+    }
+}
+
+/// A [`LineIndex`] that converts to and from LSP positions in the negotiated encoding.
+/// `LineIndex` columns are UTF-8 byte offsets, which only match the client's columns when it uses
+/// UTF-8 too.
+#[derive(Clone, Debug)]
+pub struct PositionIndex {
+    index: LineIndex,
+    encoding: PositionEncoding,
 }
 
-pub fn line_col(position: lsp_types::Position) -> LineCol {
-    LineCol {
-        line: position.line,
-        col: position.character,
+impl PositionIndex {
+    pub fn new(text: &str, encoding: PositionEncoding) -> PositionIndex {
+        PositionIndex {
+            index: LineIndex::new(text),
+            encoding,
+        }
+    }
+
+    pub fn position(&self, offset: TextSize) -> lsp_types::Position {
+        let line_col = self.index.line_col(offset);
+        let (line, character) = match self.encoding {
+            PositionEncoding::Utf8 => (line_col.line, line_col.col),
+            PositionEncoding::Wide(encoding) => {
+                let wide = self
+                    .index
+                    .to_wide(encoding, line_col)
+                    .expect("offset was not on a character boundary");
+                (wide.line, wide.col)
+            }
+        };
+        lsp_types::Position { line, character }
+    }
+
+    /// Returns `None` if the line is outside the file or the column splits a character. Columns
+    /// past the end of a line refer to the end of it, as the LSP spec requires.
+    pub fn offset(&self, position: lsp_types::Position) -> Option<TextSize> {
+        let line = self.index.line(position.line)?;
+        let col = match self.encoding {
+            PositionEncoding::Utf8 => position.character,
+            PositionEncoding::Wide(encoding) => {
+                self.index
+                    .to_utf8(
+                        encoding,
+                        WideLineCol {
+                            line: position.line,
+                            col: position.character,
+                        },
+                    )?
+                    .col
+            }
+        };
+        // Every line but the last includes its newline
+        let mut line_end = u32::from(line.end());
+        if self.index.line(position.line + 1).is_some() {
+            line_end -= 1;
+        }
+        let offset = u32::from(line.start()).saturating_add(col).min(line_end);
+        let offset = TextSize::from(offset);
+        self.index.try_line_col(offset)?;
+        Some(offset)
+    }
+
+    /// Returns `None` if either end is outside the file, or the range is reversed
+    pub fn text_range(&self, range: lsp_types::Range) -> Option<TextRange> {
+        let start = self.offset(range.start)?;
+        let end = self.offset(range.end)?;
+        (start <= end).then(|| TextRange::new(start, end))
     }
 }
 
-pub fn range(range: TextRange, index: &LineIndex) -> lsp_types::Range {
+pub fn range(range: TextRange, index: &PositionIndex) -> lsp_types::Range {
     lsp_types::Range {
-        start: position(index.line_col(range.start())),
-        end: position(index.line_col(range.end())),
+        start: index.position(range.start()),
+        end: index.position(range.end()),
     }
 }
 
+/// The token at `offset`, preferring identifiers over punctuation and trivia when the offset sits
+/// between two tokens
+pub fn token_at_offset(node: &SyntaxNode, offset: TextSize) -> Option<SyntaxToken> {
+    node.token_at_offset(offset)
+        .max_by_key(|token| match token.kind() {
+            SyntaxKind::IDENT | SyntaxKind::LIFETIME_IDENT => 3,
+            kind if kind.is_any_identifier() => 2,
+            kind if kind.is_trivia() => 0,
+            _ => 1,
+        })
+}
+
 pub trait OrLog {
     type Result;
     type Error: Display;
@@ -48,3 +146,53 @@ impl<T, E: Display> OrLog for Result<T, E> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lsp_types::Position;
+
+    fn offset(text: &str, encoding: PositionEncoding, line: u32, character: u32) -> Option<u32> {
+        PositionIndex::new(text, encoding)
+            .offset(Position { line, character })
+            .map(u32::from)
+    }
+
+    #[test]
+    fn offset_clamps_columns_to_the_line_end() {
+        let utf16 = PositionEncoding::Wide(WideEncoding::Utf16);
+        assert_eq!(offset("ab\ncd", PositionEncoding::Utf8, 0, 1), Some(1));
+        assert_eq!(offset("ab\ncd", PositionEncoding::Utf8, 0, 10), Some(2));
+        assert_eq!(
+            offset("ab\ncd", PositionEncoding::Utf8, 1, u32::MAX),
+            Some(5)
+        );
+        assert_eq!(offset("ab\n", PositionEncoding::Utf8, 1, 4), Some(3));
+        assert_eq!(offset("ab\ncd", utf16, 0, 10), Some(2));
+    }
+
+    #[test]
+    fn offset_rejects_positions_outside_the_file() {
+        assert_eq!(offset("ab\ncd", PositionEncoding::Utf8, 2, 0), None);
+        assert_eq!(offset("", PositionEncoding::Utf8, 0, 0), Some(0));
+    }
+
+    #[test]
+    fn offset_rejects_columns_inside_a_character() {
+        let utf16 = PositionEncoding::Wide(WideEncoding::Utf16);
+        assert_eq!(offset("é=1", PositionEncoding::Utf8, 0, 1), None);
+        assert_eq!(offset("é=1", PositionEncoding::Utf8, 0, 2), Some(2));
+        assert_eq!(offset("é=1", utf16, 0, 1), Some(2));
+    }
+
+    #[test]
+    fn text_range_rejects_reversed_ranges() {
+        let index = PositionIndex::new("abc", PositionEncoding::Utf8);
+        let range = |start, end| lsp_types::Range {
+            start: Position::new(0, start),
+            end: Position::new(0, end),
+        };
+        assert!(index.text_range(range(1, 2)).is_some());
+        assert!(index.text_range(range(2, 1)).is_none());
+    }
+}